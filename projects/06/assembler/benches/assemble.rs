@@ -0,0 +1,45 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use hack::model::assembler::*;
+use hack::model::cache::Cache;
+use hack::model::dialect::Dialect;
+use hack::model::isa::Isa;
+
+// Assembles Pong.asm with the single pass, with the former two passes, and
+// with a cache warmed by the previous iteration, as watch mode does.
+fn assemble(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("hack-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let src = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../pong/Pong.asm");
+    let path = dir.join("Pong.asm");
+    std::fs::copy(&src, &path).unwrap();
+    let mut group = c.benchmark_group("Pong.asm");
+    group.sample_size(20);
+    group.bench_function("single pass", |b| {
+        b.iter(|| {
+            let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+            assembler.set_verbose(false);
+            assembler.run().unwrap();
+        })
+    });
+    group.bench_function("two passes", |b| {
+        b.iter(|| {
+            let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+            assembler.set_verbose(false);
+            assembler.run_two_pass().unwrap();
+        })
+    });
+    let mut cache = Some(Cache::new());
+    group.bench_function("cached", |b| {
+        b.iter(|| {
+            let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+            assembler.set_verbose(false);
+            assembler.set_cache(cache.take().unwrap());
+            assembler.run().unwrap();
+            cache = assembler.take_cache();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, assemble);
+criterion_main!(benches);
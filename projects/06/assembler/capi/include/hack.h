@@ -0,0 +1,62 @@
+/* Generated from src/lib.rs by `HACK_BLESS=1 cargo test -p hack-capi`, do not edit. */
+#ifndef HACK_H
+#define HACK_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define HACK_ABI_VERSION 1
+
+/* The outcome of hack_assemble, freed with hack_free. */
+typedef struct hack_result hack_result;
+
+/* One diagnostic; the strings live as long as the result. */
+typedef struct hack_diagnostic {
+    /* 0-based source line */
+    size_t line;
+    /* 1 for an error, 0 for a warning */
+    int32_t error;
+    /* e.g. "E003", "L002" or "warning", see hack explain */
+    const char *code;
+    const char *message;
+} hack_diagnostic;
+
+/* HACK_ABI_VERSION of the library, to check against the header's. */
+uint32_t hack_abi_version(void);
+
+/* Assembles len bytes of Hack assembly, which need not end in a NUL. Never returns NULL; free the result with hack_free. */
+hack_result *hack_assemble(const char *source, size_t len);
+
+/* 1 if the program assembled, 0 if a diagnostic is an error. */
+int32_t hack_ok(const hack_result *result);
+
+/* The ROM image, one instruction per word, its length stored in len. Empty unless hack_ok. */
+const uint16_t *hack_words(const hack_result *result, size_t *len);
+
+/* Number of diagnostics, in source order. */
+size_t hack_diagnostic_count(const hack_result *result);
+
+/* Diagnostic index, NULL past the last one. */
+const hack_diagnostic *hack_diagnostic_at(const hack_result *result, size_t index);
+
+/* Looks up a label, variable or predefined symbol: 1 and its address in value if it is known, 0 otherwise. */
+int32_t hack_symbol(const hack_result *result, const char *name, int32_t *value);
+
+/* Number of symbols the program defines: its labels, then its variables. */
+size_t hack_symbol_count(const hack_result *result);
+
+/* Name of symbol index, its address in value; NULL past the last one. */
+const char *hack_symbol_at(const hack_result *result, size_t index, int32_t *value);
+
+/* Frees a result; NULL is ignored. */
+void hack_free(hack_result *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
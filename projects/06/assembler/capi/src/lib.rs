@@ -0,0 +1,550 @@
+//! C ABI of the assembler
+//!
+//! Assembles a buffer in-process, the way `hack-lsp` analyses a document:
+//! every line is checked, so a result carries all diagnostics (errors,
+//! `.warning`s and lints) instead of stopping at the first error, and the
+//! words only when there is no error. Quoted `.include`s are read relative
+//! to the working directory.
+//!
+//! include/hack.h is generated from this file: `HACK_BLESS=1 cargo test -p
+//! hack-capi` rewrites it, and the test fails while it is stale. Only
+//! `hack_*` functions and `Hack*` types are exported; a change to one of
+//! them that breaks callers bumps `HACK_ABI_VERSION`. A panic never
+//! unwinds into the caller: every function catches it and returns its
+//! failure value, `hack_assemble` a result with a "panic" error.
+//!
+//! From Python: `lib = ctypes.CDLL("target/debug/libhackasm.so")`, with
+//! `lib.hack_assemble.restype = ctypes.c_void_p` and the handle passed back
+//! as a `ctypes.c_void_p`.
+
+use hack::model::analysis::Analysis;
+use hack::model::isa::Isa;
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+
+pub const HACK_ABI_VERSION: u32 = 1;
+
+/// The outcome of `hack_assemble`, freed with `hack_free`.
+pub struct HackResult {
+    words: Vec<u16>,
+    diagnostics: Vec<HackDiagnostic>,
+    strings: Vec<CString>,        // what the diagnostics point to
+    symbols: Vec<(CString, i32)>, // labels, then variables
+    predefined: BTreeMap<String, i32>,
+}
+
+/// One diagnostic; the strings live as long as the result.
+#[repr(C)]
+pub struct HackDiagnostic {
+    /// 0-based source line
+    pub line: usize,
+    /// 1 for an error, 0 for a warning
+    pub error: i32,
+    /// e.g. "E003", "L002" or "warning", see `hack explain`
+    pub code: *const c_char,
+    pub message: *const c_char,
+}
+
+fn string(s: &str) -> CString {
+    CString::new(s.replace('\0', " ")).unwrap()
+}
+
+/// Runs `f`, a panic becoming its message instead of unwinding into C.
+fn guard<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    std::panic::catch_unwind(AssertUnwindSafe(f)).map_err(|e| {
+        match (e.downcast_ref::<String>(), e.downcast_ref::<&str>()) {
+            (Some(s), _) => s.clone(),
+            (_, Some(s)) => s.to_string(),
+            _ => "unknown cause".to_owned(),
+        }
+    })
+}
+
+impl HackResult {
+    /// A result holding nothing but the error of a panic.
+    fn panicked(message: &str) -> HackResult {
+        let code = string("panic");
+        let message = string(message);
+        HackResult {
+            words: Vec::new(),
+            diagnostics: vec![HackDiagnostic {
+                line: 0,
+                error: 1,
+                code: code.as_ptr(),
+                message: message.as_ptr(),
+            }],
+            strings: vec![code, message],
+            symbols: Vec::new(),
+            predefined: BTreeMap::new(),
+        }
+    }
+
+    fn new(source: &str, isa: &Isa) -> HackResult {
+        let analysis = Analysis::new(source, Path::new("."), isa);
+        let mut ret = HackResult {
+            words: Vec::new(),
+            diagnostics: Vec::new(),
+            strings: Vec::new(),
+            symbols: Vec::new(),
+            predefined: isa.symbols.clone(),
+        };
+        for d in &analysis.diagnostics {
+            let code = string(&d.code);
+            let message = string(&d.message);
+            ret.diagnostics.push(HackDiagnostic {
+                line: d.line,
+                error: d.error as i32,
+                code: code.as_ptr(),
+                message: message.as_ptr(),
+            });
+            ret.strings.push(code);
+            ret.strings.push(message);
+        }
+        if ret.diagnostics.iter().all(|d| d.error == 0) {
+            let mut lines: Vec<_> = analysis.words.iter().collect();
+            lines.sort_by_key(|(num, _)| **num);
+            ret.words = lines
+                .into_iter()
+                .flat_map(|(_, words)| words)
+                .map(|w| u16::from_str_radix(w, 2).unwrap())
+                .collect();
+        }
+        let symbols = analysis.labels.iter().chain(&analysis.variables);
+        ret.symbols = symbols.map(|(k, v)| (string(k), *v as i32)).collect();
+        ret
+    }
+}
+
+/// `HACK_ABI_VERSION` of the library, to check against the header's.
+#[no_mangle]
+pub extern "C" fn hack_abi_version() -> u32 {
+    guard(|| HACK_ABI_VERSION).unwrap_or(HACK_ABI_VERSION)
+}
+
+/// Assembles `len` bytes of Hack assembly, which need not end in a NUL.
+/// Never returns NULL; free the result with `hack_free`.
+///
+/// # Safety
+///
+/// `source` points to `len` readable bytes, or `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn hack_assemble(source: *const c_char, len: usize) -> *mut HackResult {
+    let result = guard(|| {
+        let bytes = if len == 0 {
+            &[][..]
+        } else {
+            std::slice::from_raw_parts(source as *const u8, len)
+        };
+        let source = String::from_utf8_lossy(bytes);
+        HackResult::new(&source, &Isa::hack())
+    });
+    let result = result.unwrap_or_else(|e| HackResult::panicked(&e));
+    Box::into_raw(Box::new(result))
+}
+
+/// 1 if the program assembled, 0 if a diagnostic is an error.
+///
+/// # Safety
+///
+/// `result` comes from `hack_assemble` and was not freed.
+#[no_mangle]
+pub unsafe extern "C" fn hack_ok(result: *const HackResult) -> i32 {
+    guard(|| {
+        let result = &*result;
+        result.diagnostics.iter().all(|d| d.error == 0) as i32
+    })
+    .unwrap_or(0)
+}
+
+/// The ROM image, one instruction per word, its length stored in `len`.
+/// Empty unless `hack_ok`.
+///
+/// # Safety
+///
+/// `result` comes from `hack_assemble` and was not freed, `len` is writable.
+#[no_mangle]
+pub unsafe extern "C" fn hack_words(result: *const HackResult, len: *mut usize) -> *const u16 {
+    guard(|| {
+        let result = &*result;
+        *len = result.words.len();
+        result.words.as_ptr()
+    })
+    .unwrap_or_else(|_| {
+        *len = 0;
+        std::ptr::null()
+    })
+}
+
+/// Number of diagnostics, in source order.
+///
+/// # Safety
+///
+/// `result` comes from `hack_assemble` and was not freed.
+#[no_mangle]
+pub unsafe extern "C" fn hack_diagnostic_count(result: *const HackResult) -> usize {
+    guard(|| {
+        let result = &*result;
+        result.diagnostics.len()
+    })
+    .unwrap_or(0)
+}
+
+/// Diagnostic `index`, NULL past the last one.
+///
+/// # Safety
+///
+/// `result` comes from `hack_assemble` and was not freed.
+#[no_mangle]
+pub unsafe extern "C" fn hack_diagnostic_at(
+    result: *const HackResult,
+    index: usize,
+) -> *const HackDiagnostic {
+    guard(|| {
+        let result = &*result;
+        match result.diagnostics.get(index) {
+            Some(d) => d as *const HackDiagnostic,
+            None => std::ptr::null(),
+        }
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// Looks up a label, variable or predefined symbol: 1 and its address in
+/// `value` if it is known, 0 otherwise.
+///
+/// # Safety
+///
+/// `result` comes from `hack_assemble` and was not freed, `name` is a
+/// NUL-terminated string and `value` is writable.
+#[no_mangle]
+pub unsafe extern "C" fn hack_symbol(
+    result: *const HackResult,
+    name: *const c_char,
+    value: *mut i32,
+) -> i32 {
+    guard(|| {
+        let result = &*result;
+        let name = CStr::from_ptr(name);
+        let found = result
+            .symbols
+            .iter()
+            .find(|(k, _)| k.as_c_str() == name)
+            .map(|(_, v)| *v)
+            .or_else(|| {
+                let name = name.to_str().ok()?;
+                result.predefined.get(name).copied()
+            });
+        match found {
+            Some(v) => {
+                *value = v;
+                1
+            }
+            None => 0,
+        }
+    })
+    .unwrap_or(0)
+}
+
+/// Number of symbols the program defines: its labels, then its variables.
+///
+/// # Safety
+///
+/// `result` comes from `hack_assemble` and was not freed.
+#[no_mangle]
+pub unsafe extern "C" fn hack_symbol_count(result: *const HackResult) -> usize {
+    guard(|| {
+        let result = &*result;
+        result.symbols.len()
+    })
+    .unwrap_or(0)
+}
+
+/// Name of symbol `index`, its address in `value`; NULL past the last one.
+///
+/// # Safety
+///
+/// `result` comes from `hack_assemble` and was not freed, `value` is writable.
+#[no_mangle]
+pub unsafe extern "C" fn hack_symbol_at(
+    result: *const HackResult,
+    index: usize,
+    value: *mut i32,
+) -> *const c_char {
+    guard(|| {
+        let result = &*result;
+        match result.symbols.get(index) {
+            Some((name, v)) => {
+                *value = *v;
+                name.as_ptr()
+            }
+            None => std::ptr::null(),
+        }
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// Frees a result; NULL is ignored.
+///
+/// # Safety
+///
+/// `result` comes from `hack_assemble` and was not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn hack_free(result: *mut HackResult) {
+    let _ = guard(|| {
+        if !result.is_null() {
+            drop(Box::from_raw(result));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hack::model::assembler::create_assembler;
+    use hack::model::dialect::Dialect;
+
+    /// The C spelling of a Rust type in the signatures above.
+    fn c_type(rust: &str) -> String {
+        if let Some(t) = rust.strip_prefix("*const ") {
+            return format!("const {} *", c_type(t).trim_end());
+        }
+        if let Some(t) = rust.strip_prefix("*mut ") {
+            return format!("{} *", c_type(t).trim_end());
+        }
+        let t = match rust {
+            "usize" => "size_t",
+            "u16" => "uint16_t",
+            "i32" => "int32_t",
+            "u32" => "uint32_t",
+            "c_char" => "char",
+            "HackResult" => "hack_result",
+            "HackDiagnostic" => "hack_diagnostic",
+            _ => panic!("no C type for {}", rust),
+        };
+        format!("{} ", t)
+    }
+
+    /// `/* ... */` from the doc lines, up to the Safety section.
+    fn comment(doc: &[&str], indent: &str) -> String {
+        let doc: Vec<&str> = doc
+            .iter()
+            .take_while(|l| **l != "# Safety")
+            .copied()
+            .collect();
+        let doc = doc.join(" ");
+        let doc = doc.trim();
+        if doc.is_empty() {
+            return String::new();
+        }
+        format!("{}/* {} */\n", indent, doc.replace('`', ""))
+    }
+
+    /// include/hack.h, from the declarations of this file.
+    fn header(source: &str) -> String {
+        let mut ret = String::from(
+            "/* Generated from src/lib.rs by `HACK_BLESS=1 cargo test -p hack-capi`, do not edit. */\n\
+             #ifndef HACK_H\n#define HACK_H\n\n#include <stddef.h>\n#include <stdint.h>\n\n\
+             #ifdef __cplusplus\nextern \"C\" {\n#endif\n\n",
+        );
+        let lines: Vec<&str> = source
+            .split("#[cfg(test)]")
+            .next()
+            .unwrap()
+            .lines()
+            .map(str::trim)
+            .collect();
+        let mut doc: Vec<&str> = Vec::new();
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i];
+            i += 1;
+            if let Some(d) = line.strip_prefix("///") {
+                doc.push(d.trim());
+                continue;
+            }
+            if line.starts_with("#[") {
+                continue;
+            }
+            if let Some(v) = line.strip_prefix("pub const HACK_ABI_VERSION: u32 = ") {
+                ret.push_str(&format!(
+                    "#define HACK_ABI_VERSION {}\n\n",
+                    v.trim_end_matches(';')
+                ));
+            } else if let Some(name) = line.strip_prefix("pub struct ") {
+                let name = c_type(name.trim_end_matches(" {")).trim_end().to_owned();
+                ret.push_str(&comment(&doc, ""));
+                if lines[i - 2] != "#[repr(C)]" {
+                    ret.push_str(&format!("typedef struct {0} {0};\n\n", name));
+                } else {
+                    ret.push_str(&format!("typedef struct {} {{\n", name));
+                    let mut field_doc: Vec<&str> = Vec::new();
+                    while lines[i] != "}" {
+                        if let Some(d) = lines[i].strip_prefix("///") {
+                            field_doc.push(d.trim());
+                        } else {
+                            let field = lines[i].trim_start_matches("pub ").trim_end_matches(',');
+                            let (field, t) = field.split_at(field.find(": ").unwrap());
+                            ret.push_str(&comment(&field_doc, "    "));
+                            ret.push_str(&format!("    {}{};\n", c_type(&t[2..]), field));
+                            field_doc.clear();
+                        }
+                        i += 1;
+                    }
+                    ret.push_str(&format!("}} {};\n\n", name));
+                }
+            } else if line.contains("extern \"C\" fn ") {
+                let mut signature = line.to_owned();
+                while !signature.ends_with('{') {
+                    signature.push_str(lines[i]);
+                    i += 1;
+                }
+                let signature = signature.replace(",)", ")");
+                let rest = &signature[signature.find("fn ").unwrap() + 3..];
+                let (name, rest) = rest.split_at(rest.find('(').unwrap());
+                let (params, rest) = rest[1..].split_at(rest.find(')').unwrap() - 1);
+                let ret_type = match rest[1..].trim().trim_end_matches('{').trim() {
+                    "" => "void ".to_owned(),
+                    t => c_type(t.trim_start_matches("-> ")),
+                };
+                let params: Vec<String> = params
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(|p| {
+                        let (name, t) = p.split_at(p.find(": ").unwrap());
+                        format!("{}{}", c_type(&t[2..]), name)
+                    })
+                    .collect();
+                let params = if params.is_empty() {
+                    "void".to_owned()
+                } else {
+                    params.join(", ")
+                };
+                ret.push_str(&comment(&doc, ""));
+                ret.push_str(&format!("{}{}({});\n\n", ret_type, name, params));
+            }
+            doc.clear();
+        }
+        ret.push_str("#ifdef __cplusplus\n}\n#endif\n\n#endif\n");
+        ret
+    }
+
+    #[test]
+    fn test_header() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let generated = header(&std::fs::read_to_string(dir.join("src/lib.rs")).unwrap());
+        let path = dir.join("include/hack.h");
+        if std::env::var_os("HACK_BLESS").is_some() {
+            std::fs::write(&path, &generated).unwrap();
+        }
+        let current = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            current == generated,
+            "include/hack.h is stale, rerun with HACK_BLESS=1"
+        );
+    }
+
+    #[test]
+    fn test_assemble() {
+        let source = ".warning \"draft\"\n@i\nM=0\n(LOOP)\n@i\nM=M+1\n@LOOP\n0;JMP\n";
+        unsafe {
+            let result = hack_assemble(source.as_ptr() as *const c_char, source.len());
+            assert_eq!(hack_ok(result), 1);
+            let mut len = 0;
+            let words = hack_words(result, &mut len);
+            let words = std::slice::from_raw_parts(words, len);
+            assert_eq!(
+                words,
+                &[
+                    16,
+                    0b1110101010001000,
+                    16,
+                    0b1111110111001000,
+                    2,
+                    0b1110101010000111
+                ]
+            );
+            assert_eq!(hack_diagnostic_count(result), 1);
+            let d = &*hack_diagnostic_at(result, 0);
+            assert_eq!((d.line, d.error), (0, 0));
+            assert_eq!(CStr::from_ptr(d.message).to_str(), Ok("draft"));
+            assert!(hack_diagnostic_at(result, 1).is_null());
+
+            let mut value = -1;
+            assert_eq!(
+                hack_symbol(result, b"i\0".as_ptr() as *const c_char, &mut value),
+                1
+            );
+            assert_eq!(value, 16);
+            assert_eq!(
+                hack_symbol(result, b"KBD\0".as_ptr() as *const c_char, &mut value),
+                1
+            );
+            assert_eq!(value, 24576);
+            assert_eq!(
+                hack_symbol(result, b"j\0".as_ptr() as *const c_char, &mut value),
+                0
+            );
+            assert_eq!(hack_symbol_count(result), 2);
+            let name = hack_symbol_at(result, 0, &mut value);
+            assert_eq!((CStr::from_ptr(name).to_str(), value), (Ok("LOOP"), 2));
+            assert!(hack_symbol_at(result, 2, &mut value).is_null());
+            hack_free(result);
+
+            let source = "@R0\nD=Q\n0;JMP\nM=D;JXX\n";
+            let result = hack_assemble(source.as_ptr() as *const c_char, source.len());
+            assert_eq!(hack_ok(result), 0);
+            assert_eq!(hack_words(result, &mut len), (*result).words.as_ptr());
+            assert_eq!(len, 0);
+            let codes: Vec<(usize, &str)> = (0..hack_diagnostic_count(result))
+                .map(|i| &*hack_diagnostic_at(result, i))
+                .map(|d| (d.line, CStr::from_ptr(d.code).to_str().unwrap()))
+                .collect();
+            assert_eq!(codes, vec![(1, "E003"), (3, "E003"), (3, "L002")]);
+            hack_free(result);
+
+            // out of range constants are diagnostics, not panics
+            let source = "@99999\nD=A\n";
+            let result = hack_assemble(source.as_ptr() as *const c_char, source.len());
+            assert_eq!(hack_ok(result), 0);
+            let d = &*hack_diagnostic_at(result, 0);
+            assert_eq!((d.line, CStr::from_ptr(d.code).to_str()), (0, Ok("E004")));
+            hack_free(result);
+
+            // a panic is caught and reported
+            assert_eq!(guard(|| 1), Ok(1));
+            let message = guard(|| -> i32 { panic!("boom {}", 1) }).unwrap_err();
+            assert_eq!(message, "boom 1");
+            let result = Box::into_raw(Box::new(HackResult::panicked(&message)));
+            assert_eq!(hack_ok(result), 0);
+            assert_eq!(hack_words(result, &mut len), (*result).words.as_ptr());
+            assert_eq!(len, 0);
+            let d = &*hack_diagnostic_at(result, 0);
+            assert_eq!(CStr::from_ptr(d.code).to_str(), Ok("panic"));
+            assert_eq!(CStr::from_ptr(d.message).to_str(), Ok("boom 1"));
+            hack_free(result);
+        }
+    }
+
+    #[test]
+    fn test_matches_assembler() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+        let dir = std::env::temp_dir().join(format!("hack-{}-capi", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in &["../max/Max.asm", "../rect/Rect.asm", "../pong/Pong.asm"] {
+            let source = std::fs::read_to_string(root.join(name)).unwrap();
+            let path = dir.join(Path::new(name).file_name().unwrap());
+            std::fs::write(&path, &source).unwrap();
+            let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+            assembler.set_verbose(false);
+            assembler.run().unwrap();
+            let expected = std::fs::read_to_string(path.with_extension("hack")).unwrap();
+            let result = HackResult::new(&source, &Isa::hack());
+            let words: Vec<String> = result.words.iter().map(|w| format!("{:016b}", w)).collect();
+            assert_eq!(words, expected.lines().collect::<Vec<_>>(), "{}", name);
+        }
+    }
+}
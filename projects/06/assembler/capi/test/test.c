@@ -0,0 +1,124 @@
+/*
+ * Exercises libhackasm through include/hack.h:
+ *
+ *   make -C capi test
+ */
+#include <stdio.h>
+#include <string.h>
+
+#include "hack.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                   \
+    do {                                                              \
+        if (!(cond)) {                                                \
+            fprintf(stderr, "%s:%d: %s\n", __FILE__, __LINE__, #cond); \
+            failures++;                                               \
+        }                                                             \
+    } while (0)
+
+static hack_result *assemble(const char *source)
+{
+    return hack_assemble(source, strlen(source));
+}
+
+static void test_words(void)
+{
+    /* R2 = max(R0, R1) */
+    hack_result *r = assemble("@R0\n"
+                              "D=M\n"
+                              "@R1\n"
+                              "D=D-M\n"
+                              "@FIRST // jump if R0 > R1\n"
+                              "D;JGT\n"
+                              "@R1\n"
+                              "D=M\n"
+                              "@STORE\n"
+                              "0;JMP\n"
+                              "(FIRST)\n"
+                              "@R0\n"
+                              "D=M\n"
+                              "(STORE)\n"
+                              "@R2\n"
+                              "M=D\n"
+                              "(END)\n"
+                              "@END\n"
+                              "0;JMP\n");
+    size_t len = 0;
+    const uint16_t *words = hack_words(r, &len);
+    CHECK(hack_ok(r) == 1);
+    CHECK(len == 16);
+    CHECK(words[0] == 0x0000);  /* @R0 */
+    CHECK(words[1] == 0xFC10);  /* D=M */
+    CHECK(words[4] == 10);      /* @FIRST */
+    CHECK(words[15] == 0xEA87); /* 0;JMP */
+    CHECK(hack_diagnostic_count(r) == 0);
+    CHECK(hack_diagnostic_at(r, 0) == NULL);
+
+    int32_t value = -1;
+    CHECK(hack_symbol(r, "STORE", &value) == 1 && value == 12);
+    CHECK(hack_symbol(r, "SCREEN", &value) == 1 && value == 16384);
+    CHECK(hack_symbol(r, "NOWHERE", &value) == 0);
+    CHECK(hack_symbol_count(r) == 3);
+    const char *name = hack_symbol_at(r, 0, &value);
+    CHECK(name != NULL && strcmp(name, "END") == 0 && value == 14);
+    CHECK(hack_symbol_at(r, 3, &value) == NULL);
+    hack_free(r);
+}
+
+static void test_diagnostics(void)
+{
+    hack_result *r = assemble("@x\n"
+                              "D=1\n"
+                              "M=A+D\n" /* E003, not the canonical D+A */
+                              ".warning \"slow\"\n"
+                              "(LOOP)\n"
+                              "@LOOP\n"
+                              "0;JMP\n");
+    size_t len = 1;
+    hack_words(r, &len);
+    CHECK(hack_ok(r) == 0);
+    CHECK(len == 0);
+    CHECK(hack_diagnostic_count(r) == 2);
+
+    const hack_diagnostic *d = hack_diagnostic_at(r, 0);
+    CHECK(d->line == 2 && d->error == 1);
+    CHECK(strcmp(d->code, "E003") == 0);
+    CHECK(strstr(d->message, "Did you mean D+A?") != NULL);
+    d = hack_diagnostic_at(r, 1);
+    CHECK(d->line == 3 && d->error == 0);
+    CHECK(strcmp(d->code, "warning") == 0 && strcmp(d->message, "slow") == 0);
+    hack_free(r);
+}
+
+static void test_edges(void)
+{
+    hack_result *r = hack_assemble(NULL, 0);
+    size_t len = 1;
+    hack_words(r, &len);
+    CHECK(hack_ok(r) == 1 && len == 0);
+    hack_free(r);
+    hack_free(NULL);
+
+    /* not NUL-terminated */
+    const char buffer[] = {'@', '7', '\n', 'D', '=', 'A'};
+    r = hack_assemble(buffer, sizeof buffer);
+    const uint16_t *words = hack_words(r, &len);
+    CHECK(len == 2 && words[0] == 7 && words[1] == 0xEC10);
+    hack_free(r);
+}
+
+int main(void)
+{
+    CHECK(hack_abi_version() == HACK_ABI_VERSION);
+    test_words();
+    test_diagnostics();
+    test_edges();
+    if (failures) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("ok\n");
+    return 0;
+}
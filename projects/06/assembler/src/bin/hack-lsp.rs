@@ -0,0 +1,28 @@
+use structopt::StructOpt;
+
+use hack::model::isa::Isa;
+use hack::model::lsp::Server;
+
+#[derive(StructOpt)]
+#[structopt(
+    name = "hack-lsp",
+    about = "Language server for Hack assembly, speaks LSP on stdio"
+)]
+struct Cli {
+    /// Instruction set description (TOML or JSON) replacing the built-in Hack tables
+    #[structopt(long, parse(from_os_str))]
+    isa: Option<std::path::PathBuf>,
+}
+
+fn main() -> Result<(), String> {
+    let args = Cli::from_args();
+    let isa = match &args.isa {
+        Some(path) => Isa::load(path).map_err(|e| format!("{}", e))?,
+        None => Isa::hack(),
+    };
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    Server::new(isa)
+        .serve(&mut stdin.lock(), &mut stdout.lock())
+        .map_err(|e| format!("{}", e))
+}
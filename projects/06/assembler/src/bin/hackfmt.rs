@@ -0,0 +1,48 @@
+use structopt::StructOpt;
+
+use hack::model::formatter::Formatter;
+use hack::model::isa::Isa;
+
+#[derive(StructOpt)]
+#[structopt(name = "hackfmt", about = "Formats Hack assembly files in place")]
+struct Cli {
+    #[structopt(parse(from_os_str), required = true)]
+    paths: Vec<std::path::PathBuf>,
+    /// Report files that are not formatted instead of rewriting them
+    #[structopt(long)]
+    check: bool,
+    /// Instruction set description (TOML or JSON) replacing the built-in Hack tables
+    #[structopt(long, parse(from_os_str))]
+    isa: Option<std::path::PathBuf>,
+}
+
+fn main() -> Result<(), String> {
+    let args = Cli::from_args();
+    let isa = match &args.isa {
+        Some(path) => Isa::load(path).map_err(|e| format!("{}", e))?,
+        None => Isa::hack(),
+    };
+    let mut formatter = Formatter::new(isa);
+    let mut unformatted = 0;
+    for path in &args.paths {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let out = formatter
+            .format(&source)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        if out == source {
+            continue;
+        }
+        if args.check {
+            println!("{} is not formatted", path.display());
+            unformatted += 1;
+        } else {
+            std::fs::write(path, out)
+                .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+        }
+    }
+    if unformatted > 0 {
+        return Err(format!("{} file(s) not formatted", unformatted));
+    }
+    Ok(())
+}
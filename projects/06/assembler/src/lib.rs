@@ -0,0 +1,15 @@
+//! Hack assembler
+//!
+//! With `default-features = false` only the core is built, on `no_std`
+//! with `alloc`: the lexer, parser, coder, encoding tables and `HPU`,
+//! which assembles a program held in memory. The `std` feature adds
+//! everything that touches files or the terminal: the `Assembler`,
+//! directives, includes, objects, ISA description files and the tools.
+//! The `analysis` feature, part of `std`, is the subset the C API needs:
+//! directives, includes, lints and `Analysis`.
+#![cfg_attr(not(any(feature = "analysis", test)), no_std)]
+#![allow(clippy::upper_case_acronyms, clippy::enum_variant_names)]
+
+extern crate alloc;
+
+pub mod model;
@@ -1,19 +1,494 @@
+use structopt::clap::AppSettings;
 use structopt::StructOpt;
 
-mod model;
-use model::assembler::*;
+use hack::model::anatomy::Anatomy;
+use hack::model::assembler::*;
+use hack::model::batch::Batch;
+use hack::model::bitmap::Bitmap;
+use hack::model::cache::Cache;
+use hack::model::compare::Compare;
+use hack::model::dialect::Dialect;
+use hack::model::explain::Explain;
+use hack::model::isa::Isa;
+use hack::model::linker::Linker;
+use hack::model::lint::Linter;
+use hack::model::object::{Library, Object};
+use hack::model::reference::{Format, Reference};
+use hack::model::watch::Watch;
 
 #[derive(StructOpt)]
-struct Cli {
+struct AsmArgs {
+    /// Program, or a directory whose .asm files are assembled in parallel
     #[structopt(parse(from_os_str))]
-    path: std::path::PathBuf,
+    path: Option<std::path::PathBuf>,
+    /// Instruction set description (TOML or JSON) replacing the built-in Hack tables
+    #[structopt(long, parse(from_os_str))]
+    isa: Option<std::path::PathBuf>,
+    /// strict rejects commuted C-instruction spellings, relaxed rewrites them
+    #[structopt(long, default_value = "strict")]
+    dialect: Dialect,
+    /// Write the symbol-less variant of the program (Foo.asm -> FooL.asm) instead of Foo.hack
+    #[structopt(long)]
+    desymbolize: bool,
+    /// Keep comments and blank lines in the symbol-less variant
+    #[structopt(long)]
+    keep_comments: bool,
+    /// Write a relocatable object for hack link (Foo.asm -> Foo.hobj) instead of Foo.hack
+    #[structopt(short, long)]
+    compile: bool,
+    /// Remove redundant A-instructions, jumps to the next instruction and unreachable code
+    #[structopt(short = "O")]
+    optimize: bool,
+    /// Report instruction counts, ROM usage, variables and the most used labels and instructions
+    #[structopt(long)]
+    stats: bool,
+    /// Do not trace the passes
+    #[structopt(short, long)]
+    quiet: bool,
+    /// List every instruction with its encoding broken into fields instead of tracing the passes
+    #[structopt(long)]
+    explain: bool,
+    /// Check the output word by word against a reference .hack and decode every mismatch
+    #[structopt(long, parse(from_os_str))]
+    compare: Option<std::path::PathBuf>,
+    /// Also assemble the .asm files in subdirectories of a directory
+    #[structopt(short, long)]
+    recursive: bool,
+    /// Write the .hack files into this tree instead of next to the sources
+    #[structopt(long, parse(from_os_str))]
+    out_dir: Option<std::path::PathBuf>,
+    /// Number of files assembled at once, all cores by default
+    #[structopt(short, long)]
+    jobs: Option<usize>,
+    /// Reassemble whenever the program, its includes or the --isa file change
+    #[structopt(short, long)]
+    watch: bool,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Assemble a program, same as running hack without a command
+    Asm(AsmArgs),
+    /// Report Hack pitfalls, silence a rule with `// lint: allow(L001)`
+    Lint {
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<std::path::PathBuf>,
+        /// Instruction set description (TOML or JSON) replacing the built-in Hack tables
+        #[structopt(long, parse(from_os_str))]
+        isa: Option<std::path::PathBuf>,
+        /// Predefined registers the program must assign before reading, e.g. --uninit R2
+        #[structopt(long, number_of_values = 1)]
+        uninit: Vec<String>,
+    },
+    /// Write the control-flow graph as Graphviz DOT (Foo.asm -> Foo.dot)
+    Cfg {
+        #[structopt(parse(from_os_str))]
+        path: std::path::PathBuf,
+        /// Instruction set description (TOML or JSON) replacing the built-in Hack tables
+        #[structopt(long, parse(from_os_str))]
+        isa: Option<std::path::PathBuf>,
+        /// Write the call graph of VM-translated code instead (Foo.asm -> Foo.calls.dot)
+        #[structopt(long)]
+        calls: bool,
+    },
+    /// Link objects and libraries into a ROM image (a.hobj b.hobj -> a.hack)
+    Link {
+        /// Objects, linked in order, and libraries (.hlib)
+        #[structopt(parse(from_os_str), required = true)]
+        inputs: Vec<std::path::PathBuf>,
+        /// ROM image to write, the first object with .hack by default
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+        /// Link the standard library routines the program calls, e.g. std.mult
+        #[structopt(long)]
+        std: bool,
+        /// Instruction set description (TOML or JSON) the standard library is built for
+        #[structopt(long, parse(from_os_str))]
+        isa: Option<std::path::PathBuf>,
+    },
+    /// Bundle objects into a library, a member is linked only when needed
+    Lib {
+        #[structopt(parse(from_os_str), required = true)]
+        objects: Vec<std::path::PathBuf>,
+        #[structopt(short, long, parse(from_os_str))]
+        output: std::path::PathBuf,
+    },
+    /// Write assembly drawing a PBM or PNG image on the screen (ship.png -> ship.asm)
+    Bitmap {
+        #[structopt(parse(from_os_str))]
+        image: std::path::PathBuf,
+        /// Column of the image's left edge, in pixels
+        #[structopt(long, default_value = "0")]
+        x: usize,
+        /// Row of the image's top edge, in pixels
+        #[structopt(long, default_value = "0")]
+        y: usize,
+        /// Write one .data directive per row instead of instructions
+        #[structopt(long)]
+        data: bool,
+        /// Assembly to write, the image with .asm by default
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Print the instruction set reference: formats, COMP, DEST and JUMP codes, predefined symbols
+    Isa {
+        /// markdown, html or json
+        #[structopt(long, default_value = "markdown")]
+        format: Format,
+        /// Instruction set description (TOML or JSON) replacing the built-in Hack tables
+        #[structopt(long, parse(from_os_str))]
+        isa: Option<std::path::PathBuf>,
+    },
+    /// Explain an error code in detail, e.g. hack explain E004
+    Explain { code: String },
+    /// Break an instruction or 16-bit word into its fields, e.g. hack explain-instr "AM=M+1;JGT"
+    ExplainInstr {
+        instruction: String,
+        /// Instruction set description (TOML or JSON) replacing the built-in Hack tables
+        #[structopt(long, parse(from_os_str))]
+        isa: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(StructOpt)]
+#[structopt(setting = AppSettings::ArgsNegateSubcommands)]
+struct Cli {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+    #[structopt(flatten)]
+    asm: AsmArgs,
+}
+
+fn load_isa(path: &Option<std::path::PathBuf>) -> Result<Isa, String> {
+    match path {
+        Some(path) => Isa::load(path).map_err(|e| format!("{}", e)),
+        None => Ok(Isa::hack()),
+    }
+}
+
+/// An assembler for `path`, `root` is the directory --out-dir mirrors.
+fn configure(
+    path: &std::path::Path,
+    root: &std::path::Path,
+    isa: Isa,
+    args: &AsmArgs,
+) -> Result<Assembler, String> {
+    let mut assembler: Assembler = create_assembler(path, isa, args.dialect);
+    assembler.set_optimize(args.optimize);
+    assembler.set_verbose(!args.quiet && !args.watch && !args.explain);
+    if let Some(out) = &args.out_dir {
+        let output = out
+            .join(path.strip_prefix(root).unwrap_or(path))
+            .with_extension(if args.compile { "hobj" } else { "hack" });
+        std::fs::create_dir_all(output.parent().unwrap())
+            .map_err(|e| format!("Could not create {}: {}", output.display(), e))?;
+        assembler.set_output(&output);
+    }
+    Ok(assembler)
+}
+
+fn assemble(args: AsmArgs) -> Result<(), String> {
+    let path = match &args.path {
+        Some(path) => path,
+        None => return Err("No input file, see hack --help".into()),
+    };
+    if args.watch {
+        return watch(path, &args);
+    }
+    if args.explain && (path.is_dir() || args.desymbolize || args.compile) {
+        return Err("--explain lists a single assembled file".into());
+    }
+    if args.compare.is_some() && (path.is_dir() || args.desymbolize || args.compile) {
+        return Err("--compare checks a single assembled file".into());
+    }
+    if path.is_dir() {
+        return assemble_dir(path, &args);
+    }
+    assemble_file(path, &args, &mut Cache::new(), &mut Vec::new())
+}
+
+/// Assembles one file. Lines parsed by earlier runs are taken from `cache`,
+/// the files the program was read from are left in `sources`, also when
+/// the run fails.
+fn assemble_file(
+    path: &std::path::Path,
+    args: &AsmArgs,
+    cache: &mut Cache,
+    sources: &mut Vec<std::path::PathBuf>,
+) -> Result<(), String> {
+    let isa = load_isa(&args.isa)?;
+    let root = path.parent().unwrap_or(path);
+    let mut assembler = configure(path, root, isa.clone(), args)?;
+    assembler.set_cache(std::mem::take(cache));
+    let result = if args.desymbolize {
+        assembler.desymbolize(args.keep_comments)
+    } else if args.compile {
+        assembler.compile()
+    } else {
+        assembler.run()
+    };
+    *cache = assembler.take_cache().unwrap_or_default();
+    *sources = assembler.sources().to_vec();
+    if let Err(e) = result {
+        return Err(format!("{}", e));
+    }
+    let read = |path: &std::path::Path| {
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))
+    };
+    if args.explain {
+        print!("{}", Anatomy::listing(&read(path)?, assembler.rom(), &isa));
+    }
+    if let Some(expected) = &args.compare {
+        let mismatches = Compare::diff(&read(expected)?, assembler.rom());
+        if !mismatches.is_empty() {
+            print!("{}", Compare::report(&mismatches, &read(path)?, &isa));
+            return Err(format!(
+                "{} word(s) differ from {}",
+                mismatches.len(),
+                expected.display()
+            ));
+        }
+        println!("{}: matches {}", path.display(), expected.display());
+    }
+    if args.stats && !args.desymbolize {
+        print!("{}", assembler.stats());
+    }
+    if args.watch {
+        println!(
+            "[watch]: {}: ok, {} words",
+            path.display(),
+            assembler.stats().words()
+        );
+    }
+    Ok(())
+}
+
+fn assemble_dir(dir: &std::path::Path, args: &AsmArgs) -> Result<(), String> {
+    if args.desymbolize {
+        return Err("--desymbolize takes a single file".into());
+    }
+    let isa = load_isa(&args.isa)?;
+    let files = Batch::files(dir, args.recursive)
+        .map_err(|e| format!("Could not read {}: {}", dir.display(), e))?;
+    let jobs = args
+        .jobs
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let outcomes = Batch::run(&files, jobs, |path| {
+        let mut assembler = configure(path, dir, isa.clone(), args)?;
+        assembler.set_verbose(false);
+        let result = if args.compile {
+            assembler.compile()
+        } else {
+            assembler.run()
+        };
+        result.map_err(|e| format!("{}", e))?;
+        Ok(assembler.stats().words())
+    });
+    print!("{}", Batch::summary(dir, &outcomes));
+    let failed = outcomes.iter().filter(|(_, o)| o.is_err()).count();
+    if failed > 0 {
+        return Err(format!("{} of {} file(s) failed", failed, outcomes.len()));
+    }
+    Ok(())
+}
+
+/// Reassembles `path` on every change until interrupted.
+fn watch(path: &std::path::Path, args: &AsmArgs) -> Result<(), String> {
+    let mut cache = Cache::new();
+    loop {
+        let mut files = if path.is_dir() {
+            if let Err(e) = assemble_dir(path, args) {
+                println!("[watch]: {}", e);
+            }
+            Batch::files(path, args.recursive)
+                .map_err(|e| format!("Could not read {}: {}", path.display(), e))?
+        } else {
+            let mut sources = vec![path.to_path_buf()];
+            if let Err(e) = assemble_file(path, args, &mut cache, &mut sources) {
+                println!("[watch]: {}: {}", path.display(), e.trim_end());
+            }
+            sources
+        };
+        files.extend(args.isa.clone());
+        let changed = Watch::wait(&files).map_err(|e| format!("{}", e))?;
+        println!("[watch]: {} changed", changed.display());
+    }
+}
+
+fn lint(
+    paths: &[std::path::PathBuf],
+    isa: &Option<std::path::PathBuf>,
+    uninit: &[String],
+) -> Result<(), String> {
+    let isa = load_isa(isa)?;
+    let mut count = 0;
+    for path in paths {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or_else(|| std::path::Path::new("."));
+        let lints = Linter::lint(&source, dir, &isa, uninit)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        for l in &lints {
+            println!("{}: {}", path.display(), l);
+        }
+        count += lints.len();
+    }
+    if count > 0 {
+        return Err(format!("{} warning(s)", count));
+    }
+    Ok(())
+}
+
+fn cfg(
+    path: &std::path::Path,
+    isa: &Option<std::path::PathBuf>,
+    calls: bool,
+) -> Result<(), String> {
+    let isa = load_isa(isa)?;
+    let mut assembler: Assembler = create_assembler(path, isa, Dialect::Strict);
+    let cfg = assembler.cfg().map_err(|e| format!("{}", e))?;
+    let name = path.file_stem().unwrap().to_string_lossy();
+    let (out, dot) = if calls {
+        (path.with_extension("calls.dot"), cfg.calls_dot(&name))
+    } else {
+        (path.with_extension("dot"), cfg.dot(&name))
+    };
+    std::fs::write(&out, dot).map_err(|e| format!("Could not write {}: {}", out.display(), e))?;
+    println!("[out]: {}", out.display());
+    Ok(())
+}
+
+fn link(
+    inputs: &[std::path::PathBuf],
+    output: &Option<std::path::PathBuf>,
+    std: bool,
+    isa: &Option<std::path::PathBuf>,
+) -> Result<(), String> {
+    let mut objects = Vec::new();
+    let mut libraries = Vec::new();
+    for path in inputs {
+        if path.extension().is_some_and(|e| e == "hlib") {
+            libraries.push(Library::load(path).map_err(|e| format!("{}", e))?);
+        } else {
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            objects.push((
+                path,
+                name,
+                Object::load(path).map_err(|e| format!("{}", e))?,
+            ));
+        }
+    }
+    if std {
+        let isa = load_isa(isa)?;
+        libraries.push(Library::std(&isa).map_err(|e| format!("{}", e))?);
+    }
+    let out = match (output, objects.first()) {
+        (Some(out), _) => out.clone(),
+        (None, Some((first, _, _))) => first.with_extension("hack"),
+        (None, None) => return Err("Nothing to link, give at least one object".into()),
+    };
+    let objects: Vec<(String, Object)> = objects.into_iter().map(|(_, n, o)| (n, o)).collect();
+    let rom = Linker::link(&objects, &libraries).map_err(|e| format!("{}", e))?;
+    let mut text = rom.join("\n");
+    text.push('\n');
+    std::fs::write(&out, text).map_err(|e| format!("Could not write {}: {}", out.display(), e))?;
+    println!("[out]: {} ({} words)", out.display(), rom.len());
+    Ok(())
+}
+
+fn lib(objects: &[std::path::PathBuf], output: &std::path::Path) -> Result<(), String> {
+    let mut library = Library::default();
+    for path in objects {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let object = Object::load(path).map_err(|e| format!("{}", e))?;
+        if library.members.insert(name.clone(), object).is_some() {
+            return Err(format!("Two objects are named {}", name));
+        }
+    }
+    library.save(output).map_err(|e| format!("{}", e))?;
+    println!("[out]: {}", output.display());
+    Ok(())
+}
+
+fn bitmap(
+    image: &std::path::Path,
+    x: usize,
+    y: usize,
+    data: bool,
+    output: &Option<std::path::PathBuf>,
+) -> Result<(), String> {
+    let bitmap = Bitmap::load(image).map_err(|e| format!("{}", e))?;
+    let lines = if data {
+        bitmap.data(x, y)
+    } else {
+        bitmap.code(x, y)
+    }
+    .map_err(|e| format!("{}", e))?;
+    let out = output.clone().unwrap_or_else(|| image.with_extension("asm"));
+    let mut text = format!(
+        "// {}, {}x{} at ({}, {})\n",
+        image.file_name().unwrap().to_string_lossy(),
+        bitmap.width,
+        bitmap.height,
+        x,
+        y
+    );
+    for line in &lines {
+        text.push_str(line);
+        text.push('\n');
+    }
+    std::fs::write(&out, text).map_err(|e| format!("Could not write {}: {}", out.display(), e))?;
+    println!("[out]: {}", out.display());
+    Ok(())
+}
+
+fn isa(format: Format, isa: &Option<std::path::PathBuf>) -> Result<(), String> {
+    let isa = load_isa(isa)?;
+    print!("{}", Reference::render(&isa, format));
+    Ok(())
+}
+
+fn explain(code: &str) -> Result<(), String> {
+    match Explain::explain(code) {
+        Some(text) => {
+            print!("{}", text);
+            Ok(())
+        }
+        None => Err(format!("{} is not an error code", code)),
+    }
+}
+
+fn explain_instr(instruction: &str, isa: &Option<std::path::PathBuf>) -> Result<(), String> {
+    let isa = load_isa(isa)?;
+    let text = Anatomy::instruction(instruction, &isa).map_err(|e| format!("{}", e))?;
+    print!("{}", text);
+    Ok(())
 }
 
-fn main() -> Result<(), String> {    
+fn main() -> Result<(), String> {
     let args = Cli::from_args();
-    let mut assembler: Assembler = create_assembler(&args.path);
-    match assembler.run() {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!("{}", e))
+    match args.command {
+        Some(Command::Asm(asm)) => assemble(asm),
+        Some(Command::Lint { paths, isa, uninit }) => lint(&paths, &isa, &uninit),
+        Some(Command::Cfg { path, isa, calls }) => cfg(&path, &isa, calls),
+        Some(Command::Link {
+            inputs,
+            output,
+            std,
+            isa,
+        }) => link(&inputs, &output, std, &isa),
+        Some(Command::Lib { objects, output }) => lib(&objects, &output),
+        Some(Command::Bitmap {
+            image,
+            x,
+            y,
+            data,
+            output,
+        }) => bitmap(&image, x, y, data, &output),
+        Some(Command::Isa { format, isa: path }) => isa(format, &path),
+        Some(Command::Explain { code }) => explain(&code),
+        Some(Command::ExplainInstr { instruction, isa }) => explain_instr(&instruction, &isa),
+        None => assemble(args.asm),
     }
 }
@@ -1,3 +1,4 @@
+#[cfg(feature = "std")]
 pub mod assembler;
 pub mod parser;
 pub mod lexer;
@@ -5,4 +6,48 @@ pub mod error;
 pub mod base;
 pub mod hpu;
 pub mod strutil;
-pub mod coder;
\ No newline at end of file
+pub mod coder;
+#[cfg(feature = "analysis")]
+pub mod directive;
+#[cfg(feature = "analysis")]
+pub mod expr;
+pub mod isa;
+pub mod dialect;
+#[cfg(feature = "std")]
+pub mod formatter;
+#[cfg(feature = "analysis")]
+pub mod lint;
+#[cfg(feature = "analysis")]
+pub mod cfg;
+#[cfg(feature = "analysis")]
+pub mod dataflow;
+#[cfg(feature = "std")]
+pub mod optimizer;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod watch;
+#[cfg(feature = "std")]
+pub mod explain;
+#[cfg(feature = "analysis")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod lsp;
+#[cfg(feature = "std")]
+pub mod object;
+#[cfg(feature = "std")]
+pub mod linker;
+#[cfg(feature = "analysis")]
+pub mod include;
+#[cfg(feature = "std")]
+pub mod anatomy;
+#[cfg(feature = "std")]
+pub mod compare;
+#[cfg(feature = "std")]
+pub mod bitmap;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod reference;
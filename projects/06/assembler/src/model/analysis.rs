@@ -0,0 +1,316 @@
+use super::base::ROM_SIZE;
+use super::directive::*;
+use super::error::HackError;
+use super::expr::Expr;
+use super::hpu::*;
+use super::include::Include;
+use super::isa::Isa;
+use super::lint::{Linter, RULES};
+use super::strutil::Strutil;
+use crate::hack_report_line;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/**
+ * Analysis of a document
+ *
+ * Assembles a source held in memory in a single pass, collecting one
+ * diagnostic per failing line rather than stopping at the first, then
+ * checks its asserts and ROM size and lints it. What `hack-lsp` answers
+ * hover, definition and references from, and what the C API returns.
+ * Columns are counted in UTF-16 code units, as LSP wants.
+ */
+/// A symbol as written in the source, `@NAME` or the declaration `(NAME)`.
+#[derive(Debug, PartialEq)]
+pub struct Occurrence {
+    pub name: String,
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub declaration: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub error: bool, // a warning otherwise
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    pub occurrences: Vec<Occurrence>,
+    pub labels: BTreeMap<String, usize>,    // ROM address
+    pub variables: BTreeMap<String, usize>, // RAM address
+    pub words: HashMap<usize, Vec<String>>, // encodings by source line
+}
+
+/// State of the single pass over a document.
+struct Run {
+    hpu: HPU,
+    words: Vec<(usize, String)>,  // (source line, word)
+    fixups: Vec<(usize, String)>, // (index into words, symbol)
+    asserts: Vec<(usize, String, String, String)>,
+    warnings: Vec<(usize, String)>,
+}
+
+/// Column of byte `byte` of `line`, in UTF-16 code units as LSP counts.
+pub fn column(line: &str, byte: usize) -> usize {
+    line[..byte].encode_utf16().count()
+}
+
+impl Run {
+    fn line(&mut self, num: usize, code: &str) -> Result<(), Box<HackError>> {
+        if Directive::is_check(code) {
+            match Directive::check(num, code)? {
+                Check::Assert { expr, message } => {
+                    self.asserts.push((num, code.to_owned(), expr, message))
+                }
+                Check::Error(message) => hack_report_line!(num, code, "E009", message),
+                Check::Warning(message) => self.warnings.push((num, message)),
+                Check::Export(_) => {}
+            }
+            return Ok(());
+        }
+        let lines = if Directive::is_directive(code) {
+            Directive::expand(num, code, &self.hpu.parser.isa.symbols)?
+        } else {
+            vec![code.to_owned()]
+        };
+        for l in lines {
+            match self.hpu.single_pass(num, &l)? {
+                Some(Word::Label(label)) => {
+                    let map = self.hpu.parser.map.as_mut().unwrap();
+                    map.insert(label, self.words.len());
+                }
+                Some(Word::Code(word)) => self.words.push((num, word)),
+                Some(Word::Symbol(symbol)) => {
+                    self.fixups.push((self.words.len(), symbol));
+                    self.words.push((num, String::new()));
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Analysis {
+    /// Includes are read from `dir`.
+    pub fn new(source: &str, dir: &Path, isa: &Isa) -> Analysis {
+        let mut hpu = HPU::new();
+        hpu.parser.isa = isa.clone();
+        hpu.parser.verbose = false;
+        let mut run = Run {
+            hpu,
+            words: Vec::new(),
+            fixups: Vec::new(),
+            asserts: Vec::new(),
+            warnings: Vec::new(),
+        };
+        let mut ret = Analysis::default();
+        let mut spans = HashMap::new();
+        let mut include = Include::new();
+        for (num, raw) in source.lines().enumerate() {
+            let code = Strutil::split_comment(raw).0;
+            if code.is_empty() {
+                continue;
+            }
+            let start = raw.find(code).unwrap();
+            let span = (column(raw, start), column(raw, start + code.len()));
+            spans.insert(num, span);
+            ret.occurrences
+                .extend(Analysis::occurrence(num, raw, start, code));
+            let result = include.lines(raw, dir).and_then(|lines| {
+                for (_, l) in &lines {
+                    match Strutil::split_comment(l).0 {
+                        "" => {}
+                        code => run.line(num, code)?,
+                    }
+                }
+                Ok(())
+            });
+            if let Err(e) = result {
+                ret.diagnostics.push(Diagnostic {
+                    line: num,
+                    start: span.0,
+                    end: span.1,
+                    error: true,
+                    code: e.code.to_owned(),
+                    message: e.comment,
+                });
+            }
+        }
+        let mut warn = |line: usize, code: &str, message: String, error: bool| {
+            let (start, end) = spans[&line];
+            ret.diagnostics.push(Diagnostic {
+                line,
+                start,
+                end,
+                error,
+                code: code.to_owned(),
+                message,
+            });
+        };
+        for (num, message) in run.warnings {
+            warn(num, "warning", message, false);
+        }
+        let map = run.hpu.parser.map.as_mut().unwrap();
+        let symbols = &run.hpu.parser.isa.symbols;
+        let resolve = |s: &str| match symbols.get(s) {
+            Some(n) => Some(*n as i64),
+            None => map.get(s).map(|n| *n as i64),
+        };
+        for (num, _, expr, message) in &run.asserts {
+            match Expr::eval(expr, &resolve) {
+                Ok(0) => warn(*num, "E010", format!("Assertion failed: {}", message), true),
+                Ok(_) => {}
+                Err(e) => warn(*num, "E011", e, true),
+            }
+        }
+        if let Ok(lints) = Linter::lint(source, dir, isa, &[]) {
+            for l in lints {
+                let rule = RULES.iter().find(|(id, _)| *id == l.id).unwrap().1;
+                let message = match &l.name {
+                    Some(name) => format!("{} {}", rule, name),
+                    None => rule.to_owned(),
+                };
+                warn(l.line_num, l.id, message, false);
+            }
+        }
+        if let Some((num, _)) = run.words.get(ROM_SIZE) {
+            warn(
+                *num,
+                "E007",
+                format!(
+                    "ROM overflow: the program needs more than {} words",
+                    ROM_SIZE
+                ),
+                true,
+            );
+        }
+        ret.labels = map.clone();
+        let mut varmem = *run.hpu.parser.varmem.as_ref().unwrap();
+        for (i, symbol) in run.fixups {
+            let address = *map.entry(symbol.clone()).or_insert_with(|| {
+                ret.variables.insert(symbol, varmem);
+                varmem += 1;
+                varmem - 1
+            });
+            run.words[i].1 = format!("0{:015b}", address);
+        }
+        for (num, word) in run.words {
+            ret.words.entry(num).or_insert_with(Vec::new).push(word);
+        }
+        ret.diagnostics.sort_by_key(|d| d.line);
+        ret
+    }
+
+    /// The symbol of `@NAME` or `(NAME)`, `code` starting at byte `start` of `raw`.
+    fn occurrence(num: usize, raw: &str, start: usize, code: &str) -> Option<Occurrence> {
+        let (name, declaration) = if let Some(rest) = code.strip_prefix('@') {
+            (rest, false)
+        } else if code.starts_with('(') && code.ends_with(')') && code.len() > 1 {
+            (&code[1..code.len() - 1], true)
+        } else {
+            return None;
+        };
+        let name = name.trim();
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        let offset = start + code.find(name).unwrap();
+        Some(Occurrence {
+            name: name.to_owned(),
+            line: num,
+            start: column(raw, offset),
+            end: column(raw, offset + name.len()),
+            declaration,
+        })
+    }
+
+    /// The symbol under `character` of `line`.
+    pub fn symbol_at(&self, line: usize, character: usize) -> Option<&Occurrence> {
+        self.occurrences
+            .iter()
+            .find(|o| o.line == line && o.start <= character && character <= o.end)
+    }
+
+    /// Where a label is declared, or a variable first used.
+    pub fn definition(&self, name: &str) -> Option<&Occurrence> {
+        self.occurrences
+            .iter()
+            .find(|o| o.name == name && o.declaration)
+            .or_else(|| {
+                if !self.variables.contains_key(name) {
+                    return None;
+                }
+                self.occurrences.iter().find(|o| o.name == name)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "// counts n down from 10\n\
+                           @10\n\
+                           D=A\n\
+                           @n\n\
+                           M=D\n\
+                           (LOOP)\n\
+                           \x20 @n\n\
+                           \x20 MD=M-1\n\
+                           \x20 @LOOP\n\
+                           \x20 D;JGT\n\
+                           (END)\n\
+                           \x20 @END\n\
+                           \x20 0;JMP\n";
+
+    #[test]
+    fn test_analysis() {
+        let analysis = Analysis::new(PROGRAM, Path::new("."), &Isa::hack());
+        assert_eq!(analysis.diagnostics, vec![]);
+        assert_eq!(analysis.labels["LOOP"], 4);
+        assert_eq!(analysis.variables["n"], 16);
+        assert_eq!(analysis.words[&8], vec!["0000000000000100"]);
+        let o = analysis.symbol_at(8, 4).unwrap();
+        assert_eq!((o.name.as_str(), o.start, o.end), ("LOOP", 3, 7));
+        assert_eq!(analysis.definition("n").unwrap().line, 3);
+
+        let broken = PROGRAM.replace("D=A", "D=Q").replace("@10", "@-10");
+        let analysis = Analysis::new(&broken, Path::new("."), &Isa::hack());
+        let codes: Vec<(usize, &str)> = analysis
+            .diagnostics
+            .iter()
+            .map(|d| (d.line, d.code.as_str()))
+            .collect();
+        assert_eq!(codes, vec![(1, "E004"), (2, "E003")]);
+        assert_eq!(
+            analysis.diagnostics[1].message,
+            "Q is not defined in table COMP!"
+        );
+    }
+
+    #[test]
+    fn test_rom_overflow() {
+        let source = "D=0\n".repeat(ROM_SIZE + 2);
+        let analysis = Analysis::new(&source, Path::new("."), &Isa::hack());
+        let codes: Vec<(usize, &str)> = analysis
+            .diagnostics
+            .iter()
+            .map(|d| (d.line, d.code.as_str()))
+            .collect();
+        assert_eq!(codes, vec![(ROM_SIZE, "E007")]);
+        let source = "D=0\n".repeat(ROM_SIZE);
+        assert_eq!(
+            Analysis::new(&source, Path::new("."), &Isa::hack()).diagnostics,
+            vec![]
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use super::error::*;
+use super::hpu::{Word, HPU};
+use super::isa::Isa;
+use super::strutil::Strutil;
+use crate::hack_report_less;
+use std::collections::BTreeMap;
+
+/**
+ * Instruction anatomy
+ *
+ * Splits an encoded word into its fields, the way projects/05/CPU.hdl
+ * decodes it. A C-instruction of the Hack layout is
+ *
+ * 1 1 1 a c1 c2 c3 c4 c5 c6 d1 d2 d3 j1 j2 j3
+ *
+ * a picks A or M as the ALU's y input, c1..c6 are the ALU control bits
+ * zx nx zy ny f no, d1..d3 store the result in A, D and M, and j1..j3
+ * jump when it is negative, zero or positive. Mnemonics come from the
+ * COMP, DEST and JUMP tables of the ISA, so an extended ISA with the same
+ * layout is explained as well; other layouts are split into prefix,
+ * comp, dest and jump only.
+ */
+pub struct Anatomy {}
+
+#[derive(Debug, PartialEq)]
+pub struct Field {
+    pub bits: String,
+    pub name: &'static str,
+    pub meaning: String,
+}
+
+pub const ALU: &str = "\
+the ALU computes out from x = D and y = A or M:
+  zx  x = 0        nx  x = !x
+  zy  y = 0        ny  y = !y
+  f   out = x + y if 1, x & y if 0
+  no  out = !out
+";
+
+fn width(table: &BTreeMap<String, String>) -> usize {
+    Isa::none(table).len()
+}
+
+/// The mnemonic encoded as `bits`, `no dest` for zeros missing from the table.
+fn mnemonic(table: &BTreeMap<String, String>, bits: &str, name: &str) -> String {
+    match table.iter().find(|(_, v)| *v == bits) {
+        Some((k, _)) => format!("{} {}", name, k),
+        None if !bits.contains('1') => format!("no {}", name),
+        None => format!("not in the {} table", name.to_uppercase()),
+    }
+}
+
+impl Anatomy {
+    /// Whether the C-instructions of `isa` have the prefix, a, c, d and j bits of Hack.
+    pub fn hack_layout(isa: &Isa) -> bool {
+        isa.prefix.len() == 3
+            && width(&isa.comp) == 7
+            && width(&isa.dest) == 3
+            && width(&isa.jump) == 3
+    }
+
+    /// Registers the d1 d2 d3 bits of a Hack C-instruction store the result in.
+    pub fn stores(d: &str) -> Vec<&'static str> {
+        Anatomy::set(d, ["A", "D", "M"])
+    }
+
+    /// Conditions on the result the j1 j2 j3 bits of a Hack C-instruction jump on.
+    pub fn conditions(j: &str) -> Vec<&'static str> {
+        Anatomy::set(j, ["out < 0", "out = 0", "out > 0"])
+    }
+
+    fn set(bits: &str, names: [&'static str; 3]) -> Vec<&'static str> {
+        bits.bytes()
+            .zip(names.iter())
+            .filter(|(b, _)| *b == b'1')
+            .map(|(_, n)| *n)
+            .collect()
+    }
+
+    /// Fields of a 16-bit `word`, from the most significant bit on.
+    pub fn fields(word: &str, isa: &Isa) -> Vec<Field> {
+        let field = |bits: &str, name, meaning: String| Field {
+            bits: bits.to_owned(),
+            name,
+            meaning,
+        };
+        if let Some(bits) = word.strip_prefix('0') {
+            let value = usize::from_str_radix(bits, 2).unwrap_or(0);
+            return vec![
+                field("0", "op", "A-instruction".into()),
+                field(bits, "value", format!("A = {}", value)),
+            ];
+        }
+        let p = isa.prefix.len();
+        let j = 16 - width(&isa.jump);
+        let d = j - width(&isa.dest);
+        let prefix = if word[..p] == isa.prefix {
+            "C-instruction".to_owned()
+        } else {
+            format!("not a C-instruction, which starts with {}", isa.prefix)
+        };
+        let comp = mnemonic(&isa.comp, &word[p..d], "comp");
+        let dest = mnemonic(&isa.dest, &word[d..j], "dest");
+        let jump = mnemonic(&isa.jump, &word[j..], "jump");
+        if !Anatomy::hack_layout(isa) {
+            return vec![
+                field(&word[..p], "prefix", prefix),
+                field(&word[p..d], "comp", comp),
+                field(&word[d..j], "dest", dest),
+                field(&word[j..], "jump", jump),
+            ];
+        }
+        let bit = |i: usize| &word[i..i + 1] == "1";
+        let y = if bit(3) { "y is M, RAM[A]" } else { "y is A" };
+        let flags: Vec<String> = ["zx", "nx", "zy", "ny", "f", "no"]
+            .iter()
+            .enumerate()
+            .map(|(i, f)| format!("{}={}", f, &word[4 + i..5 + i]))
+            .collect();
+        let stores = Anatomy::stores(&word[10..13]);
+        let stores = match stores.len() {
+            0 => "stores nothing".to_owned(),
+            _ => format!("stores out in {}, {}", stores.join(" and "), dest),
+        };
+        let conditions = Anatomy::conditions(&word[13..]);
+        let jumps = match conditions.len() {
+            0 => "never jumps".to_owned(),
+            3 => format!("always jumps to A, {}", jump),
+            _ => format!("jumps to A if {}, {}", conditions.join(" or "), jump),
+        };
+        vec![
+            field(&word[..3], "prefix", prefix),
+            field(&word[3..4], "a", y.to_owned()),
+            field(&word[4..10], "c", format!("{}, {}", flags.join(" "), comp)),
+            field(&word[10..13], "d", stores),
+            field(&word[13..], "j", jumps),
+        ]
+    }
+
+    /// The assembly of a 16-bit `word`, e.g. `AM=M+1;JGT`, with `?` before
+    /// bits no mnemonic encodes.
+    pub fn disassemble(word: &str, isa: &Isa) -> String {
+        if word.len() != 16 || !word.bytes().all(|b| b == b'0' || b == b'1') {
+            return "not a 16-bit word".to_owned();
+        }
+        if let Some(bits) = word.strip_prefix('0') {
+            return format!("@{}", usize::from_str_radix(bits, 2).unwrap());
+        }
+        let p = isa.prefix.len();
+        let j = 16 - width(&isa.jump);
+        let d = j - width(&isa.dest);
+        if word[..p] != isa.prefix {
+            return format!("?{}", word);
+        }
+        let lookup = |table: &BTreeMap<String, String>, bits: &str| match table
+            .iter()
+            .find(|(_, v)| *v == bits)
+        {
+            Some((k, _)) => k.clone(),
+            None if !bits.contains('1') => String::new(),
+            None => format!("?{}", bits),
+        };
+        let dest = lookup(&isa.dest, &word[d..j]);
+        let jump = lookup(&isa.jump, &word[j..]);
+        let mut ret = String::new();
+        if !dest.is_empty() {
+            ret.push_str(&dest);
+            ret.push('=');
+        }
+        ret.push_str(&lookup(&isa.comp, &word[p..d]));
+        if !jump.is_empty() {
+            ret.push(';');
+            ret.push_str(&jump);
+        }
+        ret
+    }
+
+    /// One line per field of `word`, each indented by `indent`.
+    pub fn explain(word: &str, isa: &Isa, indent: &str) -> String {
+        Anatomy::fields(word, isa)
+            .iter()
+            .map(|f| format!("{}{:<16} {:<6} {}\n", indent, f.bits, f.name, f.meaning))
+            .collect()
+    }
+
+    /// Encodes one instruction, or takes a 16-bit word as is, and explains its fields.
+    pub fn instruction(code: &str, isa: &Isa) -> Result<String, Box<HackError>> {
+        let code = Strutil::split_comment(code).0;
+        let word = if code.len() == 16 && code.bytes().all(|b| b == b'0' || b == b'1') {
+            code.to_owned()
+        } else {
+            let mut hpu = HPU::new();
+            hpu.parser.isa = isa.clone();
+            hpu.parser.verbose = false;
+            match hpu.single_pass(0, code)? {
+                Some(Word::Code(word)) => word,
+                Some(Word::Symbol(symbol)) => {
+                    return Ok(format!(
+                        "{}\n  0                op     A-instruction\n  \
+                         ...............  value  A = the address of {}, known once the program is assembled\n",
+                        code, symbol
+                    ))
+                }
+                Some(Word::Label(label)) => {
+                    return Ok(format!(
+                        "{}\n  declares {} as the address of the next instruction, it is not encoded\n",
+                        code, label
+                    ))
+                }
+                None => hack_report_less!("E001", "Empty line"),
+            }
+        };
+        let mut ret = format!("{}  {}\n", code, word);
+        ret.push_str(&Anatomy::explain(&word, isa, "  "));
+        if word.starts_with('1') && Anatomy::hack_layout(isa) {
+            ret.push('\n');
+            ret.push_str(ALU);
+        }
+        Ok(ret)
+    }
+
+    /// Every word of `rom` under the line of `source` it was assembled from.
+    pub fn listing(source: &str, rom: &[(usize, String)], isa: &Isa) -> String {
+        let lines: Vec<&str> = source.lines().collect();
+        let mut ret = String::new();
+        let mut last = None;
+        for (address, (num, word)) in rom.iter().enumerate() {
+            if last != Some(num) {
+                let line = lines.get(*num).map_or("", |l| Strutil::split_comment(l).0);
+                ret.push_str(&format!("[{}]: {}\n", num, line));
+                last = Some(num);
+            }
+            ret.push_str(&format!("  {:<5} {}\n", address, word));
+            ret.push_str(&Anatomy::explain(word, isa, "    "));
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(word: &str, isa: &Isa) -> Vec<(String, String)> {
+        Anatomy::fields(word, isa)
+            .into_iter()
+            .map(|f| (f.bits, f.meaning))
+            .collect()
+    }
+
+    #[test]
+    fn test_fields() {
+        let isa = Isa::hack();
+        let pairs = |v: &[(&str, &str)]| -> Vec<(String, String)> {
+            v.iter()
+                .map(|(a, b)| (a.to_string(), b.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            summary("1111110111101001", &isa),
+            pairs(&[
+                ("111", "C-instruction"),
+                ("1", "y is M, RAM[A]"),
+                ("110111", "zx=1 nx=1 zy=0 ny=1 f=1 no=1, comp M+1"),
+                ("101", "stores out in A and M, dest AM"),
+                ("001", "jumps to A if out > 0, jump JGT"),
+            ])
+        );
+        assert_eq!(
+            summary("1110001100000101", &isa)[3..],
+            pairs(&[
+                ("000", "stores nothing"),
+                ("101", "jumps to A if out < 0 or out > 0, jump JNE"),
+            ])[..]
+        );
+        assert_eq!(
+            summary("0000000000010101", &isa),
+            pairs(&[("0", "A-instruction"), ("000000000010101", "A = 21")])
+        );
+        assert_eq!(
+            summary("1111111111000111", &isa)[2].1,
+            "zx=1 nx=1 zy=1 ny=1 f=1 no=1, not in the COMP table"
+        );
+    }
+
+    #[test]
+    fn test_other_layout() {
+        let mut isa = Isa::hack();
+        isa.prefix = "11".into();
+        isa.comp = isa
+            .comp
+            .iter()
+            .map(|(k, v)| (k.clone(), format!("0{}", v)))
+            .collect();
+        let fields = Anatomy::fields("1100001100000111", &isa);
+        let names: Vec<&str> = fields.iter().map(|f| f.name).collect();
+        assert_eq!(names, vec!["prefix", "comp", "dest", "jump"]);
+        assert_eq!(fields[1].meaning, "comp D");
+        assert_eq!(fields[3].meaning, "jump JMP");
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let isa = Isa::hack();
+        let cases = [
+            ("1111110111101001", "AM=M+1;JGT"),
+            ("1110101010000111", "0;JMP"),
+            ("1110001100001000", "M=D"),
+            ("0000000000010101", "@21"),
+            ("1111111111000111", "?1111111;JMP"),
+            ("1000001100001000", "?1000001100001000"),
+            ("10", "not a 16-bit word"),
+        ];
+        for (word, code) in &cases {
+            assert_eq!(Anatomy::disassemble(word, &isa), *code);
+        }
+    }
+
+    #[test]
+    fn test_instruction() {
+        let isa = Isa::hack();
+        let text = Anatomy::instruction("AM=M+1;JGT // count", &isa).unwrap();
+        assert!(text.starts_with("AM=M+1;JGT  1111110111101001\n  111"));
+        assert!(text.ends_with(ALU));
+        let text = Anatomy::instruction("1111110111101001", &isa).unwrap();
+        assert!(text.contains("comp M+1"));
+        assert!(Anatomy::instruction("@LOOP", &isa)
+            .unwrap()
+            .contains("address of LOOP"));
+        assert!(Anatomy::instruction("(LOOP)", &isa)
+            .unwrap()
+            .contains("not encoded"));
+        let e = Anatomy::instruction("AM=M+D", &isa).unwrap_err();
+        assert_eq!(e.code, "E003");
+    }
+
+    #[test]
+    fn test_listing() {
+        let rom = vec![
+            (0, "0000000000000111".to_owned()),
+            (0, "1110110000010000".to_owned()),
+            (2, "1110101010000111".to_owned()),
+        ];
+        let text = Anatomy::listing(".data 7 // twice\n\n0;JMP\n", &rom, &Isa::hack());
+        let headers: Vec<&str> = text.lines().filter(|l| l.starts_with('[')).collect();
+        assert_eq!(headers, vec!["[0]: .data 7", "[2]: 0;JMP"]);
+        assert!(text.contains("  1     1110110000010000\n    111 "));
+        assert_eq!(text.lines().count(), 2 + 3 + 2 + 5 + 5);
+    }
+}
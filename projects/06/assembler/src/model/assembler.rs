@@ -1,63 +1,585 @@
+use super::base::*;
+use super::cache::{Cache, Entry};
+use super::cfg::Cfg;
+use super::dialect::Dialect;
+use super::directive::*;
+use super::expr::*;
 use super::hpu::*;
+use super::include::Include;
+use super::isa::Isa;
+use super::object::{Object, Slot};
+use super::optimizer::Optimizer;
+use super::stats::Stats;
+use super::strutil::Strutil;
 use std::boxed::Box;
+use std::collections::HashMap;
 use std::fs::File;
 use super::error::*;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use crate::{hack_report_less};
+use std::io::{BufWriter, Write};
+use crate::{hack_report_less, hack_report_line};
 
 
-pub fn create_assembler(path: &std::path::PathBuf) -> Assembler {
+pub fn create_assembler(path: &std::path::Path, isa: Isa, dialect: Dialect) -> Assembler {
+    let mut hpu = HPU::new();
+    hpu.parser.isa = isa;
+    hpu.parser.dialect = dialect;
     Assembler {
-        path: path.clone(),
-        hpu: HPU::new(path),
+        path: path.to_path_buf(),
+        hpu,
+        asserts: Vec::new(),
+        exports: Vec::new(),
+        optimize: false,
+        optimized: None,
+        stats: Stats::default(),
+        output: None,
+        sources: vec![path.to_path_buf()],
+        text: None,
+        rom: Vec::new(),
+        cache: None,
     }
 }
 
 pub struct Assembler {
     path: std::path::PathBuf,
     hpu: HPU,
+    // (line number, line, expression, message), checked once every symbol is known
+    asserts: Vec<(usize, String, String, String)>,
+    exports: Vec<(usize, String, String)>, // (line number, line, label) named by .export
+    optimize: bool,
+    optimized: Option<Vec<(usize, String)>>, // the optimized source, shared by both passes
+    stats: Stats,
+    output: Option<std::path::PathBuf>, // .hack file, next to the source if None
+    sources: Vec<std::path::PathBuf>,
+    text: Option<String>, // the program, read from `path` if None
+    rom: Vec<(usize, String)>, // (source line, word) of the last run
+    cache: Option<Cache>,
 }
 
 impl Assembler {
+    /// Runs the peephole optimizer before the passes, see `Optimizer`.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Writes the .hack file to `path` instead of next to the source.
+    pub fn set_output(&mut self, path: &std::path::Path) {
+        self.output = Some(path.to_path_buf());
+    }
+
+    /// Assembles `text` instead of the contents of the file.
+    pub fn set_source(&mut self, text: &str) {
+        self.text = Some(text.to_owned());
+    }
+
+    /// Files the program is read from, the ones watch mode waits on.
+    pub fn sources(&self) -> &[std::path::PathBuf] {
+        &self.sources
+    }
+
+    /// Every word of the last `run`, with the source line it was assembled from.
+    pub fn rom(&self) -> &[(usize, String)] {
+        &self.rom
+    }
+
+    /// Reuses the lines `cache` parsed in earlier runs, see `Cache`.
+    pub fn set_cache(&mut self, cache: Cache) {
+        self.cache = Some(cache);
+    }
+
+    /// The cache, holding the lines of the last `run`, for the next assembler.
+    pub fn take_cache(&mut self) -> Option<Cache> {
+        self.cache.take()
+    }
+
+    /// Statistics of the last `run`.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
     pub fn run(&mut self) -> Result<(), Box<HackError>> {
+        self.single_pass()
+    }
+
+    /// The former pipeline, reading and parsing the source once per pass.
+    pub fn run_two_pass(&mut self) -> Result<(), Box<HackError>> {
         self.first_pass()?;
         self.second_pass()?;
+        self.check_written()
+    }
+
+    /// Writes a relocatable object for `hack link`, e.g. Max.hobj for Max.asm.
+    pub fn compile(&mut self) -> Result<(), Box<HackError>> {
+        let object = self.object()?;
+        match self.output.take() {
+            Some(output) => self.path = output,
+            None => {
+                self.path.set_extension("hobj");
+            }
+        }
+        self.trace(&format!("[out]: {}", self.path.display()));
+        object.save(&self.path)
+    }
+
+    /// The relocatable object, without writing it.
+    pub fn object(&mut self) -> Result<Object, Box<HackError>> {
+        let object = self.compile_pass()?;
+        self.check_asserts()?;
+        Ok(object)
+    }
+
+    /// Writes the symbol-less variant of the program, e.g. MaxL.asm for Max.asm.
+    pub fn desymbolize(&mut self, keep_comments: bool) -> Result<(), Box<HackError>> {
+        self.first_pass()?;
+        let lines = self.desymbolize_pass(keep_comments)?;
+        self.check_asserts()?;
+        let stem = match self.path.file_stem() {
+            Some(stem) => stem.to_string_lossy().into_owned(),
+            None => hack_report_less!(
+                "E008",
+                format!(
+                    "Could not write next to {}: it has no file name",
+                    self.path.display()
+                )
+            ),
+        };
+        self.path.set_file_name(format!("{}L.asm", stem));
+        let mut writer = self.create()?;
+        for l in lines {
+            if writeln!(&mut writer, "{}", l).is_err() {
+                hack_report_less!("E008", "Error occured in writeln!")
+            }
+        }
         Ok(())
     }
+
+    /// Control-flow graph over the instructions, labels resolved by the first pass.
+    pub fn cfg(&mut self) -> Result<Cfg, Box<HackError>> {
+        self.first_pass()?;
+        let mut code = Vec::new();
+        for (num, line) in self.source()? {
+            if line.is_empty() || Directive::is_check(&line) {
+                continue;
+            }
+            for l in self.expand(num, line)? {
+                if HPU::command_type(&l) != CommandType::LCommand {
+                    code.push((num, l.split_whitespace().collect()));
+                }
+            }
+        }
+        Ok(Cfg::build(
+            &code,
+            self.hpu.parser.map.as_ref().unwrap(),
+            &self.hpu.parser.isa.symbols,
+        ))
+    }
+
+    /// Prints pass progress unless `set_verbose(false)` was called.
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.hpu.parser.verbose = verbose;
+    }
+
+    fn trace(&self, s: &str) {
+        if self.hpu.parser.verbose {
+            println!("{}", s);
+        }
+    }
+
     fn polish(s: &str) -> String {
-        s.trim().to_owned()
+        Strutil::split_comment(s).0.to_owned()
+    }
+
+    /// Lines the passes see for one source line: directives are expanded.
+    fn expand(&self, num: usize, line: String) -> Result<Vec<String>, Box<HackError>> {
+        if Directive::is_directive(&line) {
+            Directive::expand(num, &line, &self.hpu.parser.isa.symbols)
+        } else {
+            Ok(vec![line])
+        }
     }
+
+    /// Expands and parses one source line, symbols are left unresolved.
+    fn parse(hpu: &mut HPU, num: usize, line: String) -> Result<Entry, Box<HackError>> {
+        let lines = if Directive::is_directive(&line) {
+            Directive::expand(num, &line, &hpu.parser.isa.symbols)?
+        } else {
+            vec![line]
+        };
+        let mut entry = Vec::new();
+        for l in lines {
+            let word = hpu.parse(num, &l)?;
+            entry.push((l, word));
+        }
+        Ok(entry)
+    }
+
+    fn check(&mut self, num: usize, line: String) -> Result<(), Box<HackError>> {
+        match Directive::check(num, &line)? {
+            Check::Assert { expr, message } => self.asserts.push((num, line, expr, message)),
+            Check::Error(message) => hack_report_line!(num, line, "E009", message),
+            Check::Warning(message) => println!("[warning]: [{}]: {}", num, message),
+            Check::Export(names) => {
+                for name in names {
+                    self.exports.push((num, line.clone(), name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&self) -> Result<String, Box<HackError>> {
+        if let Some(text) = &self.text {
+            return Ok(text.clone());
+        }
+        match std::fs::read_to_string(&self.path) {
+            Ok(text) => Ok(text),
+            Err(e) => hack_report_less!("E008", format!("Could not read {}: {}", self.path.display(), e)),
+        }
+    }
+
+    /// Directory includes are relative to.
+    fn dir(&self) -> &std::path::Path {
+        self.path.parent().unwrap_or_else(|| std::path::Path::new("."))
+    }
+
+    /// Source lines the passes read, expanded and optimized with `set_optimize`.
+    fn source(&mut self) -> Result<Vec<(usize, String)>, Box<HackError>> {
+        if let Some(lines) = &self.optimized {
+            return Ok(lines.clone());
+        }
+        let mut include = Include::new();
+        let lines = include.lines(&self.read()?, self.dir());
+        // the includes read so far are kept for watch mode even on an error
+        self.sources.truncate(1);
+        self.sources.append(&mut include.files);
+        let lines = lines?;
+        let lines = lines
+            .into_iter()
+            .map(|(num, line)| (num, Assembler::polish(&line)));
+        if !self.optimize {
+            return Ok(lines.collect());
+        }
+        let mut expanded = Vec::new();
+        for (num, line) in lines {
+            if line.is_empty() {
+                continue;
+            }
+            if Directive::is_check(&line) {
+                expanded.push((num, line));
+                continue;
+            }
+            for l in self.expand(num, line)? {
+                expanded.push((num, l.split_whitespace().collect()));
+            }
+        }
+        let size = |lines: &[(usize, String)]| {
+            lines.iter().filter(|(_, l)| Optimizer::is_code(l)).count()
+        };
+        let before = size(&expanded);
+        let optimized = Optimizer::optimize(expanded, &self.hpu.parser.isa);
+        self.trace(&format!("[opt]: {} -> {} words", before, size(&optimized)));
+        self.optimized = Some(optimized.clone());
+        Ok(optimized)
+    }
+
     fn first_pass(&mut self) -> Result<(), Box<HackError>> {
-        println!("================= First Pass Begins =================");
-        let f = File::open(&self.path).expect("Could not read file");
-        let reader = BufReader::new(f);
-        for (num, line) in reader.lines().enumerate() {
-            self.hpu
-                .first_pass(&(num, Assembler::polish(&line.unwrap())))?;
-        }
-        println!("================= First Pass Ends =================");
+        self.trace("================= First Pass Begins =================");
+        for (num, line) in self.source()? {
+            for l in self.expand(num, line)? {
+                self.hpu.first_pass(&(num, l.clone()))?;
+                if self.hpu.valid_line > ROM_SIZE {
+                    hack_report_line!(
+                        num,
+                        l,
+                        "E007",
+                        format!("ROM overflow: the program needs more than {} words", ROM_SIZE)
+                    )
+                }
+            }
+        }
+        self.trace("================= First Pass Ends =================");
         Ok(())
     }
 
     fn second_pass(&mut self) -> Result<(), Box<HackError>> {
-        println!("================= Second Pass Begins =================");
-        let f = File::open(&self.path).expect("Could not read file");
-        &self.path.set_extension("hack");
-        let w = File::create(&self.path).expect("Could not read file");
-        let mut writer = BufWriter::new(w);
-        let reader = BufReader::new(f);
-        for (num, line) in reader.lines().enumerate() {
-            let out = self
-                .hpu
-                .second_pass(num, &Assembler::polish(&line.unwrap()))?;
-            println!("[out]: {}", out);
-            if out.len() > 0 {
-                if let Err(_) = writeln!(&mut writer, "{}", out) {
-                        hack_report_less!("Error occured in writeln!")
+        self.trace("================= Second Pass Begins =================");
+        let source = self.source()?;
+        self.path.set_extension("hack");
+        let mut writer = self.create()?;
+        self.stats = Stats::new(self.hpu.parser.map.as_ref().unwrap().keys().cloned().collect());
+        for (num, line) in source {
+            if Directive::is_check(&line) {
+                self.check(num, line)?;
+                continue;
+            }
+            let directive = Directive::is_directive(&line).then(|| line.clone());
+            let lines = self.expand(num, line)?;
+            if let Some(d) = directive {
+                self.trace(&format!("[dir]: {} ({} words)", d, Directive::size(&lines)));
+            }
+            for l in lines {
+                if !l.is_empty() {
+                    self.stats.add(&l);
+                }
+                let out = self.hpu.second_pass(num, &l)?;
+                self.trace(&format!("[out]: {}", out));
+                if !out.is_empty() && writeln!(&mut writer, "{}", out).is_err() {
+                    hack_report_less!("E008", "Error occured in writeln!")
                 }
             }
         }
-        println!("================= Second Pass Ends =================");
+        self.stats.variables = self.hpu.parser.varmem.unwrap() - VARMEM_BASE;
+        self.trace("================= Second Pass Ends =================");
+        Ok(())
+    }
+
+    /// Parses every line once, A-instructions naming a label defined further down
+    /// or a variable are patched at the end, variables in order of first use.
+    fn single_pass(&mut self) -> Result<(), Box<HackError>> {
+        self.trace("================= Single Pass Begins =================");
+        let source = self.source()?;
+        let mut words: Vec<String> = Vec::new();
+        let mut origins: Vec<usize> = Vec::new(); // source line of every word
+        let mut fixups: Vec<(usize, String)> = Vec::new(); // (index into words, symbol)
+        let mut code: Vec<String> = Vec::new(); // expanded lines, for the statistics
+        if let Some(cache) = &mut self.cache {
+            cache.begin(&self.hpu.parser.isa, self.hpu.parser.dialect);
+        }
+        for (num, line) in source {
+            if Directive::is_check(&line) {
+                self.check(num, line)?;
+                continue;
+            }
+            let directive = Directive::is_directive(&line).then(|| line.clone());
+            let entry = match self.cache.as_mut().and_then(|c| c.get(&line)) {
+                Some(entry) => entry.clone(),
+                None => {
+                    let entry = Assembler::parse(&mut self.hpu, num, line.clone())?;
+                    if let Some(cache) = &mut self.cache {
+                        cache.insert(line, entry.clone());
+                    }
+                    entry
+                }
+            };
+            if let Some(d) = directive {
+                let size = entry.iter().filter(|(l, _)| !l.starts_with('(')).count();
+                self.trace(&format!("[dir]: {} ({} words)", d, size));
+            }
+            for (l, word) in entry {
+                match self.hpu.resolve(word) {
+                    Some(Word::Label(label)) => {
+                        self.hpu.parser.map.as_mut().unwrap().insert(label, words.len());
+                    }
+                    Some(Word::Code(word)) => words.push(word),
+                    Some(Word::Symbol(symbol)) => {
+                        fixups.push((words.len(), symbol));
+                        words.push(String::new());
+                    }
+                    None => continue,
+                }
+                origins.resize(words.len(), num);
+                if words.len() > ROM_SIZE {
+                    hack_report_line!(
+                        num,
+                        l,
+                        "E007",
+                        format!("ROM overflow: the program needs more than {} words", ROM_SIZE)
+                    )
+                }
+                code.push(l);
+            }
+        }
+        let map = self.hpu.parser.map.as_mut().unwrap();
+        self.stats = Stats::new(map.keys().cloned().collect());
+        let varmem = self.hpu.parser.varmem.as_mut().unwrap();
+        for (i, symbol) in fixups {
+            let address = *map.entry(symbol).or_insert_with(|| {
+                *varmem += 1;
+                *varmem - 1
+            });
+            words[i] = format!("0{:015b}", address);
+        }
+        self.stats.variables = *varmem - VARMEM_BASE;
+        for l in &code {
+            self.stats.add(l);
+        }
+        match self.output.take() {
+            Some(output) => self.path = output,
+            None => {
+                self.path.set_extension("hack");
+            }
+        }
+        self.check_asserts()?;
+        let mut writer = self.create()?;
+        for word in &words {
+            self.trace(&format!("[out]: {}", word));
+            if writeln!(&mut writer, "{}", word).is_err() {
+                hack_report_less!("E008", "Error occured in writeln!")
+            }
+        }
+        self.rom = origins.into_iter().zip(words).collect();
+        if let Some(cache) = &mut self.cache {
+            let (hits, misses) = (cache.hits, cache.misses);
+            cache.end();
+            self.trace(&format!("[cache]: {} of {} lines reused", hits, hits + misses));
+        }
+        self.trace("================= Single Pass Ends =================");
+        Ok(())
+    }
+
+    /// The single pass without the symbol table: labels stay object-relative,
+    /// so every A-instruction naming a symbol becomes a relocation or an external.
+    fn compile_pass(&mut self) -> Result<Object, Box<HackError>> {
+        self.trace("================= Compile Pass Begins =================");
+        let mut labels: HashMap<String, usize> = HashMap::new();
+        let mut object = Object::default();
+        let mut code: Vec<String> = Vec::new(); // expanded lines, for the statistics
+        for (num, line) in self.source()? {
+            if Directive::is_check(&line) {
+                self.check(num, line)?;
+                continue;
+            }
+            for l in self.expand(num, line)? {
+                match self.hpu.single_pass(num, &l)? {
+                    Some(Word::Label(label)) => {
+                        labels.insert(label, object.code.len());
+                    }
+                    Some(Word::Code(word)) => object.code.push(Slot::Word(word)),
+                    Some(Word::Symbol(symbol)) => object.code.push(Slot::External(symbol)),
+                    None => continue,
+                }
+                if object.code.len() > ROM_SIZE {
+                    hack_report_line!(
+                        num,
+                        l,
+                        "E007",
+                        format!("ROM overflow: the program needs more than {} words", ROM_SIZE)
+                    )
+                }
+                code.push(l);
+            }
+        }
+        for slot in object.code.iter_mut() {
+            if let Slot::External(symbol) = slot {
+                if let Some(offset) = labels.get(symbol) {
+                    *slot = Slot::Relocate(*offset);
+                }
+            }
+        }
+        for (num, line, name) in &self.exports {
+            match labels.get(name) {
+                Some(offset) => {
+                    object.exports.insert(name.clone(), *offset);
+                }
+                None => hack_report_line!(
+                    *num,
+                    line,
+                    "E017",
+                    format!("{} is exported but not defined", name)
+                ),
+            }
+        }
+        self.stats = Stats::new(labels.keys().cloned().collect());
+        for l in &code {
+            self.stats.add(l);
+        }
+        // .assert sees the labels at their offsets into the object
+        self.hpu.parser.map.as_mut().unwrap().extend(labels);
+        self.trace("================= Compile Pass Ends =================");
+        Ok(object)
+    }
+
+    /// The symbol-less lines, kept in memory so a failed run writes nothing.
+    fn desymbolize_pass(&mut self, keep_comments: bool) -> Result<Vec<String>, Box<HackError>> {
+        self.trace("================= Desymbolize Pass Begins =================");
+        let text = self.read()?;
+        let dir = self.dir().to_path_buf();
+        let mut ret = Vec::new();
+        let mut include = Include::new();
+        for (num, raw) in text.lines().enumerate() {
+            let (code, comment) = Strutil::split_comment(raw);
+            // an include is written out in full, symbol-less too
+            let lines = if Include::is_include(code) {
+                include
+                    .lines(code, &dir)?
+                    .iter()
+                    .map(|(_, l)| Assembler::polish(l))
+                    .collect()
+            } else {
+                vec![code.to_owned()]
+            };
+            let mut out: Vec<String> = Vec::new();
+            for line in lines {
+                if Directive::is_check(&line) {
+                    self.check(num, line)?;
+                    continue;
+                } else if line.is_empty() {
+                    continue;
+                }
+                for l in self.expand(num, line)? {
+                    let word = self.hpu.second_pass(num, &l)?;
+                    match HPU::command_type(&l) {
+                        CommandType::ACommand => {
+                            out.push(format!("@{}", i64::from_str_radix(&word[1..], 2).unwrap()))
+                        }
+                        CommandType::CCommand => out.push(l),
+                        CommandType::LCommand => {}
+                    }
+                }
+            }
+            if keep_comments {
+                match (out.last_mut(), comment) {
+                    (Some(last), Some(c)) => *last = format!("{} {}", last, c),
+                    (None, Some(c)) if code.is_empty() => out.push(c.to_owned()),
+                    (None, None) if code.is_empty() => out.push(String::new()),
+                    _ => {}
+                }
+            }
+            ret.append(&mut out);
+        }
+        self.trace("================= Desymbolize Pass Ends =================");
+        Ok(ret)
+    }
+
+    /// Opens the output file, `self.path`.
+    fn create(&self) -> Result<BufWriter<File>, Box<HackError>> {
+        match File::create(&self.path) {
+            Ok(w) => Ok(BufWriter::new(w)),
+            Err(e) => hack_report_less!(
+                "E008",
+                format!("Could not write {}: {}", self.path.display(), e)
+            ),
+        }
+    }
+
+    /// `check_asserts` for the second pass, which writes as it goes: the
+    /// output of a failed run is removed rather than left behind.
+    fn check_written(&self) -> Result<(), Box<HackError>> {
+        let ret = self.check_asserts();
+        if ret.is_err() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+        ret
+    }
+
+    fn check_asserts(&self) -> Result<(), Box<HackError>> {
+        let map = self.hpu.parser.map.as_ref().unwrap();
+        let symbols = &self.hpu.parser.isa.symbols;
+        let resolve = |s: &str| match symbols.get(s) {
+            Some(n) => Some(*n as i64),
+            None => map.get(s).map(|n| *n as i64),
+        };
+        for (num, line, expr, message) in &self.asserts {
+            match Expr::eval(expr, &resolve) {
+                Ok(0) => hack_report_line!(
+                    *num,
+                    line,
+                    "E010",
+                    format!("Assertion failed: {}", message)
+                ),
+                Ok(_) => {}
+                Err(e) => hack_report_line!(*num, line, "E011", e),
+            }
+        }
         Ok(())
     }
 }
@@ -65,4 +587,241 @@ impl Assembler {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::model::linker::Linker;
+
+    /// Copies `name` into a directory of its own for the test `test`.
+    fn copy_to_temp(name: &str, test: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("hack-{}-{}", std::process::id(), test));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(name);
+        let dst = dir.join(src.file_name().unwrap());
+        std::fs::copy(&src, &dst).unwrap();
+        dst
+    }
+
+    fn code_lines(path: &std::path::Path) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|l| Strutil::split_comment(l).0.to_owned())
+            .filter(|l| !l.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn test_desymbolize() {
+        for name in ["../max/Max.asm", "../rect/Rect.asm", "../pong/Pong.asm"].iter() {
+            let path = copy_to_temp(name, "desymbolize");
+            let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+            assembler.desymbolize(false).unwrap();
+            let expected = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join(name.replace(".asm", "L.asm"));
+            assert_eq!(code_lines(&assembler.path), code_lines(&expected));
+        }
+    }
+
+    #[test]
+    fn test_desymbolize_keep_comments() {
+        let path = copy_to_temp("../max/Max.asm", "keep_comments");
+        let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+        assembler.desymbolize(true).unwrap();
+        let out = std::fs::read_to_string(&assembler.path).unwrap();
+        assert!(out.starts_with("// This file is part of www.nand2tetris.org\n"));
+        assert!(out.contains("@0\nD=M // D = first number\n"));
+        assert!(!out.contains("(OUTPUT_FIRST)"));
+    }
+
+    #[test]
+    fn test_desymbolize_failures() {
+        let dir =
+            std::env::temp_dir().join(format!("hack-{}-desymbolize-fail", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Broken.asm");
+        std::fs::write(&path, "@1\nD=A\n.include \"missing.asm\"\n@2\n").unwrap();
+        let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+        assembler.set_verbose(false);
+        assert!(assembler.desymbolize(false).is_err());
+        assert!(!dir.join("BrokenL.asm").exists());
+        let path = dir.join("no-such-dir/Foo.asm");
+        let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+        assembler.set_verbose(false);
+        assembler.set_source("@1\n");
+        assert_eq!(assembler.desymbolize(false).unwrap_err().code, "E008");
+    }
+
+    #[test]
+    fn test_sources_after_error() {
+        let dir = std::env::temp_dir().join(format!("hack-{}-sources", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Main.asm");
+        std::fs::write(&path, "@1\n.include \"lib.asm\"\n").unwrap();
+        // an error in the pass, one while splicing, a missing include
+        for lib in ["D=Q\n", "(std.x)\n", ".include \"gone.asm\"\n"].iter() {
+            std::fs::write(dir.join("lib.asm"), lib).unwrap();
+            let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+            assembler.set_verbose(false);
+            assert!(assembler.run().is_err());
+            assert_eq!(assembler.sources()[..2], [path.clone(), dir.join("lib.asm")]);
+            if lib.starts_with(".include") {
+                assert_eq!(assembler.sources().last(), Some(&dir.join("gone.asm")));
+            }
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let path = copy_to_temp("../rect/Rect.asm", "stats");
+        let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+        assembler.run().unwrap();
+        let stats = assembler.stats();
+        assert_eq!((stats.a, stats.c, stats.labels), (12, 13, 2));
+        assert_eq!(stats.variables, 2);
+        assert_eq!(stats.label_uses["LOOP"], 1);
+    }
+
+    #[test]
+    fn test_rom_overflow() {
+        let dir = std::env::temp_dir().join(format!("hack-{}-overflow", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Overflow.asm");
+        std::fs::write(&path, "D=0\n".repeat(ROM_SIZE + 1)).unwrap();
+        let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+        let e = assembler.run().unwrap_err();
+        assert_eq!(e.source_line_num, Some(ROM_SIZE));
+        std::fs::write(&path, "D=0\n".repeat(ROM_SIZE)).unwrap();
+        let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+        assert!(assembler.run().is_ok());
+    }
+
+    #[test]
+    fn test_failed_assert_writes_nothing() {
+        let dir = std::env::temp_dir().join(format!("hack-{}-assert", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Assert.asm");
+        std::fs::write(
+            &path,
+            "@END\n(END)\n.assert END > 5, \"too short, add code\"\n",
+        )
+        .unwrap();
+        for mode in 0..4 {
+            let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+            assembler.set_verbose(false);
+            let result = match mode {
+                0 => assembler.run(),
+                1 => assembler.run_two_pass(),
+                2 => assembler.compile(),
+                _ => assembler.desymbolize(false),
+            };
+            let e = result.unwrap_err();
+            assert_eq!(e.code, "E010");
+            for out in ["Assert.hack", "Assert.hobj", "AssertL.asm"].iter() {
+                assert!(!dir.join(out).exists(), "{}", out);
+            }
+        }
+    }
+
+    #[test]
+    fn test_optimize_keeps_table() {
+        let dir = std::env::temp_dir().join(format!("hack-{}-optimize", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Table.asm");
+        let text = "@BACK\nD=A\n@R15\nM=D\n@2\nD=A\n@T\n0;JMP\n(BACK)\n@BACK\n0;JMP\n\
+                    .table T 10, 20, 30\n";
+        let mut words = Vec::new();
+        for optimize in [false, true].iter() {
+            let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+            assembler.set_verbose(false);
+            assembler.set_source(text);
+            assembler.set_optimize(*optimize);
+            assembler.run().unwrap();
+            words.push(
+                assembler
+                    .rom()
+                    .iter()
+                    .map(|(_, w)| w.clone())
+                    .collect::<Vec<_>>(),
+            );
+        }
+        assert_eq!(words[0].len(), 10 + 4 * 3 + 9);
+        assert_eq!(words[1], words[0]);
+    }
+
+    #[test]
+    fn test_unwritable_output() {
+        let path = std::env::temp_dir()
+            .join(format!("hack-{}-unwritable", std::process::id()))
+            .join("no-such-dir/Foo.asm");
+        let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+        assembler.set_verbose(false);
+        assembler.set_source("@1\n");
+        assert_eq!(assembler.run_two_pass().unwrap_err().code, "E008");
+    }
+
+    #[test]
+    fn test_single_pass_matches_two_pass() {
+        let names = ["../max/Max.asm", "../rect/Rect.asm", "../pong/Pong.asm", "../pong/PongL.asm"];
+        for name in names.iter() {
+            let path = copy_to_temp(name, "single_pass");
+            let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+            assembler.set_verbose(false);
+            assembler.run_two_pass().unwrap();
+            let two_pass = std::fs::read_to_string(&assembler.path).unwrap();
+            let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+            assembler.set_verbose(false);
+            assembler.run().unwrap();
+            assert_eq!(std::fs::read_to_string(&assembler.path).unwrap(), two_pass);
+        }
+    }
+
+    #[test]
+    fn test_cache_matches_uncached() {
+        let path = copy_to_temp("../pong/Pong.asm", "cache");
+        let original = std::fs::read_to_string(&path).unwrap();
+        let run = |text: &str, cache: Option<Cache>| {
+            let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+            assembler.set_verbose(false);
+            assembler.set_source(text);
+            if let Some(cache) = cache {
+                assembler.set_cache(cache);
+            }
+            let result = assembler.run().map(|_| assembler.rom().to_vec());
+            (result, assembler.take_cache())
+        };
+        let (rom, cache) = run(&original, Some(Cache::new()));
+        let cache = cache.unwrap();
+        assert_eq!(cache.misses, cache.len());
+        assert_eq!(rom.unwrap(), run(&original, None).0.unwrap());
+        // shifts every label below and adds a variable
+        let edited = original.replacen("@256", "@256\n@cached\nM=D", 1);
+        assert_ne!(edited, original);
+        let (rom, cache) = run(&edited, cache.into());
+        let cache = cache.unwrap();
+        assert_eq!(cache.misses, 1);
+        assert_eq!(cache.hits + 1, edited.lines().count());
+        assert_eq!(rom.unwrap(), run(&edited, None).0.unwrap());
+        // errors are not cached and keep their line number
+        let broken = edited.replacen("@cached\n", "@cached\nD=Q\n", 1);
+        let (e, cache) = run(&broken, cache.into());
+        let line = broken.lines().position(|l| l == "D=Q").unwrap();
+        assert_eq!(e.unwrap_err().source_line_num, Some(line));
+        let (rom, _) = run(&edited, cache);
+        assert_eq!(rom.unwrap(), run(&edited, None).0.unwrap());
+    }
+
+    #[test]
+    fn test_compile_and_link() {
+        for name in ["../max/Max.asm", "../rect/Rect.asm", "../pong/Pong.asm"].iter() {
+            let path = copy_to_temp(name, "compile");
+            let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+            assembler.set_verbose(false);
+            assembler.compile().unwrap();
+            let object = Object::load(&path.with_extension("hobj")).unwrap();
+            let rom = Linker::link(&[("main".into(), object)], &[]).unwrap();
+            let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+            assembler.set_verbose(false);
+            assembler.run().unwrap();
+            let expected = std::fs::read_to_string(path.with_extension("hack")).unwrap();
+            assert_eq!(rom, expected.lines().collect::<Vec<_>>(), "{}", name);
+        }
+    }
 }
@@ -1,89 +1,87 @@
-use lazy_static::lazy_static;
-use std::collections::HashMap;
+use alloc::string::String;
 
-lazy_static! {
-    pub static ref DEST: HashMap<String, &'static str> = {
-        let mut m = HashMap::new();
-        m.insert(String::from("M"), "001");
-        m.insert(String::from("D"), "010");
-        m.insert(String::from("MD"), "011");
-        m.insert(String::from("A"), "100");
-        m.insert(String::from("AM"), "101");
-        m.insert(String::from("AD"), "110");
-        m.insert(String::from("AMD"), "111");
-        m
-    };
-    pub static ref JUMP: HashMap<String, &'static str> = {
-        let mut m = HashMap::new();
-        m.insert(String::from("JGT"), "001");
-        m.insert(String::from("JEQ"), "010");
-        m.insert(String::from("JGE"), "011");
-        m.insert(String::from("JLT"), "100");
-        m.insert(String::from("JNE"), "101");
-        m.insert(String::from("JLE"), "110");
-        m.insert(String::from("JMP"), "111");
-        m
-    };
-    pub static ref PREDEFINE_SYMBOLS: HashMap<String, i32> = {
-        let mut m = HashMap::new();
-        m.insert(String::from("SP"), 0);
-        m.insert(String::from("LCL"), 1);
-        m.insert(String::from("ARG"), 2);
-        m.insert(String::from("THIS"), 3);
-        m.insert(String::from("THAT"), 4);
-        m.insert(String::from("SCREEN"), 16384);
-        m.insert(String::from("KBD"), 24576);
-        m.insert(String::from("R0"), 0);
-        m.insert(String::from("R1"), 1);
-        m.insert(String::from("R2"), 2);
-        m.insert(String::from("R3"), 3);
-        m.insert(String::from("R4"), 4);
-        m.insert(String::from("R5"), 5);
-        m.insert(String::from("R6"), 6);
-        m.insert(String::from("R7"), 7);
-        m.insert(String::from("R8"), 8);
-        m.insert(String::from("R9"), 9);
-        m.insert(String::from("R10"), 10);
-        m.insert(String::from("R11"), 11);
-        m.insert(String::from("R12"), 12);
-        m.insert(String::from("R13"), 13);
-        m.insert(String::from("R14"), 14);
-        m.insert(String::from("R15"), 15);
-        m
-    };
-    pub static ref COMP: HashMap<String, &'static str> = {
-        let mut m = HashMap::new();
-        m.insert(String::from("0"),   "0101010");
-        m.insert(String::from("1"),   "0111111");
-        m.insert(String::from("-1"),  "0111010");
-        m.insert(String::from("D"),   "0001100");
-        m.insert(String::from("A"),   "0110000");
-        m.insert(String::from("M"),   "1110000");
-        m.insert(String::from("!D"),  "0001101");
-        m.insert(String::from("!A"),  "0110001");
-        m.insert(String::from("!M"),  "1110001");
-        m.insert(String::from("-D"),  "0001111");
-        m.insert(String::from("-A"),  "0110011");
-        m.insert(String::from("-M"),  "1110011");
-        m.insert(String::from("D+1"), "0011111");
-        m.insert(String::from("A+1"), "0110111");
-        m.insert(String::from("M+1"), "1110111");
-        m.insert(String::from("D-1"), "0001110");
-        m.insert(String::from("A-1"), "0110010");
-        m.insert(String::from("M-1"), "1110010");
-        m.insert(String::from("D+A"), "0000010");
-        m.insert(String::from("D+M"), "1000010");
-        m.insert(String::from("D-A"), "0010011");
-        m.insert(String::from("D-M"), "1010011");
-        m.insert(String::from("A-D"), "0000111");
-        m.insert(String::from("M-D"), "1000111");
-        m.insert(String::from("D&A"), "0000000");
-        m.insert(String::from("D&M"), "1000000");
-        m.insert(String::from("D|A"), "0010101");
-        m.insert(String::from("D|M"), "1010101");
-        m
-    };
-}
+pub const ROM_SIZE: usize = 32768;
+pub const VARMEM_BASE: usize = 16; // first RAM word given to a variable
+pub const SCREEN_WIDTH: usize = 512; // pixels, 32 words per row
+pub const SCREEN_HEIGHT: usize = 256;
+
+pub const DEST: [(&str, &str); 7] = [
+    ("M", "001"),
+    ("D", "010"),
+    ("MD", "011"),
+    ("A", "100"),
+    ("AM", "101"),
+    ("AD", "110"),
+    ("AMD", "111"),
+];
+
+pub const JUMP: [(&str, &str); 7] = [
+    ("JGT", "001"),
+    ("JEQ", "010"),
+    ("JGE", "011"),
+    ("JLT", "100"),
+    ("JNE", "101"),
+    ("JLE", "110"),
+    ("JMP", "111"),
+];
+
+pub const PREDEFINE_SYMBOLS: [(&str, i32); 23] = [
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("SCREEN", 16384),
+    ("KBD", 24576),
+    ("R0", 0),
+    ("R1", 1),
+    ("R2", 2),
+    ("R3", 3),
+    ("R4", 4),
+    ("R5", 5),
+    ("R6", 6),
+    ("R7", 7),
+    ("R8", 8),
+    ("R9", 9),
+    ("R10", 10),
+    ("R11", 11),
+    ("R12", 12),
+    ("R13", 13),
+    ("R14", 14),
+    ("R15", 15),
+];
+
+#[rustfmt::skip]
+pub const COMP: [(&str, &str); 28] = [
+    ("0",   "0101010"),
+    ("1",   "0111111"),
+    ("-1",  "0111010"),
+    ("D",   "0001100"),
+    ("A",   "0110000"),
+    ("M",   "1110000"),
+    ("!D",  "0001101"),
+    ("!A",  "0110001"),
+    ("!M",  "1110001"),
+    ("-D",  "0001111"),
+    ("-A",  "0110011"),
+    ("-M",  "1110011"),
+    ("D+1", "0011111"),
+    ("A+1", "0110111"),
+    ("M+1", "1110111"),
+    ("D-1", "0001110"),
+    ("A-1", "0110010"),
+    ("M-1", "1110010"),
+    ("D+A", "0000010"),
+    ("D+M", "1000010"),
+    ("D-A", "0010011"),
+    ("D-M", "1010011"),
+    ("A-D", "0000111"),
+    ("M-D", "1000111"),
+    ("D&A", "0000000"),
+    ("D&M", "1000000"),
+    ("D|A", "0010101"),
+    ("D|M", "1010101"),
+];
 
 #[derive(PartialEq, Debug)]
 pub enum CommandType {
@@ -109,19 +107,3 @@ pub struct Token {
     pub repr: String,
     pub token_type: TOKENTYPE,
 }
-
-pub struct Command {
-    pub lineno: Option<usize>,
-    pub lineno_raw: Option<usize>,
-    pub command_type: Option<CommandType>,
-}
-
-impl Command {
-    pub fn new() -> Command {
-        Command {
-            lineno: None,
-            lineno_raw: None,
-            command_type: None,
-        }
-    }
-}
\ No newline at end of file
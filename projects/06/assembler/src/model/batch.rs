@@ -0,0 +1,196 @@
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/**
+ * Batch assembly
+ *
+ * Every file is assembled by its own job on a pool of threads, each job
+ * builds its own `Assembler` and with it its own `Parser` symbol table.
+ * A job returns the number of ROM words or the first error; a job that
+ * panics fails its file and leaves the others running.
+ */
+pub struct Batch {}
+
+pub type Outcome = (PathBuf, Result<usize, String>);
+
+impl Batch {
+    /// `.asm` files in `dir`, in its subdirectories too if `recursive`, sorted.
+    pub fn files(dir: &Path, recursive: bool) -> std::io::Result<Vec<PathBuf>> {
+        let mut ret = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if recursive {
+                    ret.append(&mut Batch::files(&path, true)?);
+                }
+            } else if path.extension().is_some_and(|e| e == "asm") {
+                ret.push(path);
+            }
+        }
+        ret.sort();
+        Ok(ret)
+    }
+
+    /// Runs `job` on every file with `jobs` threads, outcomes in the order of `files`.
+    pub fn run<F>(files: &[PathBuf], jobs: usize, job: F) -> Vec<Outcome>
+    where
+        F: Fn(&Path) -> Result<usize, String> + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let outcomes: Mutex<Vec<Option<Result<usize, String>>>> =
+            Mutex::new(vec![None; files.len()]);
+        std::thread::scope(|s| {
+            for _ in 0..jobs.max(1).min(files.len()) {
+                s.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    if i >= files.len() {
+                        break;
+                    }
+                    let outcome = std::panic::catch_unwind(AssertUnwindSafe(|| job(&files[i])))
+                        .unwrap_or_else(|e| Err(Batch::panic_message(e)));
+                    outcomes.lock().unwrap()[i] = Some(outcome);
+                });
+            }
+        });
+        files
+            .iter()
+            .cloned()
+            .zip(
+                outcomes
+                    .into_inner()
+                    .unwrap()
+                    .into_iter()
+                    .map(Option::unwrap),
+            )
+            .collect()
+    }
+
+    fn panic_message(payload: Box<dyn Any + Send>) -> String {
+        let message = match payload.downcast::<String>() {
+            Ok(s) => *s,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(s) => s.to_string(),
+                Err(_) => "unknown cause".to_owned(),
+            },
+        };
+        format!("panicked: {}", message)
+    }
+
+    /// One row per file: path relative to `root`, status, words and the first error.
+    pub fn summary(root: &Path, outcomes: &[Outcome]) -> String {
+        let rows: Vec<(String, &str, String, String)> = outcomes
+            .iter()
+            .map(|(path, outcome)| {
+                let name = path
+                    .strip_prefix(root)
+                    .unwrap_or(path)
+                    .display()
+                    .to_string();
+                match outcome {
+                    Ok(words) => (name, "ok", words.to_string(), String::new()),
+                    Err(e) => (name, "FAILED", "-".to_owned(), e.trim().replace('\n', " ")),
+                }
+            })
+            .collect();
+        let width = rows.iter().map(|r| r.0.len()).max().unwrap_or(0).max(4);
+        let mut ret = format!(
+            "{:width$}  {:6}  {:>6}  error\n",
+            "file",
+            "status",
+            "words",
+            width = width
+        );
+        for (name, status, words, error) in &rows {
+            let row = format!(
+                "{:width$}  {:6}  {:>6}  {}",
+                name,
+                status,
+                words,
+                error,
+                width = width
+            );
+            ret.push_str(row.trim_end());
+            ret.push('\n');
+        }
+        let failed = outcomes.iter().filter(|(_, o)| o.is_err()).count();
+        ret.push_str(&format!(
+            "{} assembled, {} failed\n",
+            outcomes.len() - failed,
+            failed
+        ));
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files() {
+        let root = std::env::temp_dir().join(format!("hack-{}-batch", std::process::id()));
+        std::fs::create_dir_all(root.join("b/c")).unwrap();
+        for name in ["Z.asm", "a.hack", "b/X.asm", "b/c/Y.asm"].iter() {
+            std::fs::write(root.join(name), "").unwrap();
+        }
+        assert_eq!(
+            Batch::files(&root, false).unwrap(),
+            vec![root.join("Z.asm")]
+        );
+        assert_eq!(
+            Batch::files(&root, true).unwrap(),
+            vec![
+                root.join("Z.asm"),
+                root.join("b/X.asm"),
+                root.join("b/c/Y.asm")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_and_summary() {
+        let files: Vec<PathBuf> = (0..20)
+            .map(|i| PathBuf::from(format!("/r/f{:02}.asm", i)))
+            .collect();
+        let outcomes = Batch::run(&files, 4, |p| {
+            let name = p.file_stem().unwrap().to_str().unwrap();
+            match name[1..].parse::<usize>().unwrap() {
+                7 => Err("[3]: D=Q: E003 Q is not defined in table COMP!\n".to_owned()),
+                n => Ok(n),
+            }
+        });
+        assert_eq!(outcomes.len(), 20);
+        assert!(outcomes
+            .iter()
+            .enumerate()
+            .all(|(i, (p, _))| *p == files[i]));
+        let summary = Batch::summary(Path::new("/r"), &outcomes[6..9]);
+        assert_eq!(
+            summary,
+            "file     status   words  error\n\
+             f06.asm  ok           6\n\
+             f07.asm  FAILED       -  [3]: D=Q: E003 Q is not defined in table COMP!\n\
+             f08.asm  ok           8\n\
+             2 assembled, 1 failed\n"
+        );
+    }
+
+    #[test]
+    fn test_panicking_job() {
+        let files: Vec<PathBuf> = (0..4)
+            .map(|i| PathBuf::from(format!("f{}.asm", i)))
+            .collect();
+        let outcomes = Batch::run(&files, 2, |p| match p.to_str().unwrap() {
+            "f1.asm" => panic!("bad input"),
+            _ => Ok(1),
+        });
+        let results: Vec<Result<usize, String>> = outcomes.into_iter().map(|(_, o)| o).collect();
+        assert_eq!(
+            results,
+            vec![Ok(1), Err("panicked: bad input".to_owned()), Ok(1), Ok(1)]
+        );
+    }
+}
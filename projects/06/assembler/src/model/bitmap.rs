@@ -0,0 +1,329 @@
+use super::base::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use super::error::*;
+use crate::hack_report_less;
+use std::path::Path;
+
+/**
+ * Bitmap import
+ *
+ * Turns a PBM (P1 or P4) or PNG image into code that draws it on the
+ * 512x256 screen. Pixel (x, y) is bit x % 16 of the word at
+ * SCREEN + y * 32 + x / 16, the leftmost pixel being the least
+ * significant bit. A PNG pixel is black when it is darker than mid-grey
+ * and more opaque than not.
+ *
+ * Drawing overwrites whole words: the words an image covers are
+ * replaced, including the pixels of a partly covered word that lie
+ * outside the image. The code form stores every word with the fewest
+ * instructions and skips reloading D for repeated values; the data form
+ * is one `.data` line per row, 4 words per value once expanded.
+ */
+#[derive(Debug)]
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<bool>, // row-major, true is black
+}
+
+const SCREEN: usize = 16384;
+const WORDS_PER_ROW: usize = SCREEN_WIDTH / 16;
+
+fn malformed<T>(msg: &str) -> Result<T, Box<HackError>> {
+    hack_report_less!("E019", format!("Malformed image: {}", msg))
+}
+
+/// Number of pixels of a `width` x `height` image, which must not be empty.
+fn area(width: usize, height: usize) -> Result<usize, Box<HackError>> {
+    if width == 0 || height == 0 {
+        return malformed(&format!("a {}x{} image is empty", width, height));
+    }
+    match width.checked_mul(height) {
+        Some(area) => Ok(area),
+        None => malformed(&format!("a {}x{} image is too large", width, height)),
+    }
+}
+
+impl Bitmap {
+    /// Reads a PBM or PNG image, told apart by their magic numbers.
+    pub fn load(path: &Path) -> Result<Bitmap, Box<HackError>> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                hack_report_less!("E008", format!("Could not read {}: {}", path.display(), e))
+            }
+        };
+        Bitmap::decode(&bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Bitmap, Box<HackError>> {
+        if bytes.starts_with(b"\x89PNG") {
+            Bitmap::png(bytes)
+        } else if bytes.starts_with(b"P1") || bytes.starts_with(b"P4") {
+            Bitmap::pbm(bytes)
+        } else {
+            malformed("expect a PBM (P1 or P4) or PNG image")
+        }
+    }
+
+    /// A plain (P1) or raw (P4) portable bitmap, 1 is black.
+    pub fn pbm(bytes: &[u8]) -> Result<Bitmap, Box<HackError>> {
+        let mut i = 2;
+        let mut header = [0; 2];
+        for n in header.iter_mut() {
+            loop {
+                while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                    i += 1;
+                }
+                if i < bytes.len() && bytes[i] == b'#' {
+                    while i < bytes.len() && bytes[i] != b'\n' {
+                        i += 1;
+                    }
+                } else {
+                    break;
+                }
+            }
+            let start = i;
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+            *n = match std::str::from_utf8(&bytes[start..i]).unwrap().parse() {
+                Ok(n) => n,
+                Err(_) => return malformed("the PBM header lacks a width or height"),
+            };
+        }
+        let (width, height) = (header[0], header[1]);
+        let area = area(width, height)?;
+        // a single whitespace separates the header from the raster
+        let raster = &bytes[(i + 1).min(bytes.len())..];
+        let pixels: Vec<bool> = if bytes[1] == b'1' {
+            raster
+                .iter()
+                .filter(|b| !b.is_ascii_whitespace())
+                .take(area)
+                .map(|b| match b {
+                    b'0' => Ok(false),
+                    b'1' => Ok(true),
+                    _ => malformed("a P1 pixel is 0 or 1"),
+                })
+                .collect::<Result<_, _>>()?
+        } else {
+            let stride = width.div_ceil(8);
+            if stride.checked_mul(height).is_none_or(|n| raster.len() < n) {
+                return malformed("the P4 raster is shorter than its header says");
+            }
+            (0..area)
+                .map(|p| {
+                    let (row, col) = (p / width, p % width);
+                    raster[row * stride + col / 8] & (0x80 >> (col % 8)) != 0
+                })
+                .collect()
+        };
+        if pixels.len() < area {
+            return malformed("the P1 raster is shorter than its header says");
+        }
+        Ok(Bitmap {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn png(bytes: &[u8]) -> Result<Bitmap, Box<HackError>> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = match decoder.read_info() {
+            Ok(reader) => reader,
+            Err(e) => return malformed(&e.to_string()),
+        };
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = match reader.next_frame(&mut buf) {
+            Ok(info) => info,
+            Err(e) => return malformed(&e.to_string()),
+        };
+        let channels = info.color_type.samples();
+        let (width, height) = (info.width as usize, info.height as usize);
+        let pixels = (0..area(width, height)?)
+            .map(|p| {
+                let px = &buf[p / width * info.line_size + p % width * channels..][..channels];
+                let (luma, alpha) = match px {
+                    [l] => (*l as u32, 255),
+                    [l, a] => (*l as u32, *a),
+                    [r, g, b] => (
+                        (*r as u32 * 299 + *g as u32 * 587 + *b as u32 * 114) / 1000,
+                        255,
+                    ),
+                    [r, g, b, a] => (
+                        (*r as u32 * 299 + *g as u32 * 587 + *b as u32 * 114) / 1000,
+                        *a,
+                    ),
+                    _ => (255, 0),
+                };
+                luma < 128 && alpha >= 128
+            })
+            .collect();
+        Ok(Bitmap {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// The screen words covered by the image drawn with its top left corner
+    /// at (`x`, `y`), one row of (offset from SCREEN, word) pairs per image row.
+    pub fn words(&self, x: usize, y: usize) -> Result<Vec<Vec<(usize, u16)>>, Box<HackError>> {
+        let fits = |at: usize, size: usize, screen: usize| {
+            at.checked_add(size).is_some_and(|end| end <= screen)
+        };
+        if !fits(x, self.width, SCREEN_WIDTH) || !fits(y, self.height, SCREEN_HEIGHT) {
+            hack_report_less!(
+                "E020",
+                format!(
+                    "A {}x{} image at ({}, {}) does not fit on the {}x{} screen",
+                    self.width, self.height, x, y, SCREEN_WIDTH, SCREEN_HEIGHT
+                )
+            )
+        }
+        let first = x / 16;
+        let last = (x + self.width).div_ceil(16);
+        Ok((0..self.height)
+            .map(|row| {
+                let base = (y + row) * WORDS_PER_ROW;
+                let mut words: Vec<(usize, u16)> =
+                    (first..last).map(|col| (base + col, 0)).collect();
+                for c in 0..self.width {
+                    if self.pixels[row * self.width + c] {
+                        words[(x + c) / 16 - first].1 |= 1 << ((x + c) % 16);
+                    }
+                }
+                words
+            })
+            .collect())
+    }
+
+    /// Straight-line code drawing the image at (`x`, `y`).
+    pub fn code(&self, x: usize, y: usize) -> Result<Vec<String>, Box<HackError>> {
+        let mut ret = Vec::new();
+        let mut d = None;
+        for (offset, word) in self.words(x, y)?.into_iter().flatten() {
+            let comp = match word as i16 {
+                0 => "0",
+                1 => "1",
+                -1 => "-1",
+                w => {
+                    if d != Some(w) {
+                        if w >= 0 {
+                            ret.push(format!("@{}", w));
+                            ret.push("D=A".into());
+                        } else if w == i16::MIN {
+                            ret.push("@32767".into());
+                            ret.push("D=!A".into());
+                        } else {
+                            ret.push(format!("@{}", -w));
+                            ret.push("D=-A".into());
+                        }
+                        d = Some(w);
+                    }
+                    "D"
+                }
+            };
+            ret.push(format!("@{}", SCREEN + offset));
+            ret.push(format!("M={}", comp));
+        }
+        Ok(ret)
+    }
+
+    /// One `.data` directive per image row.
+    pub fn data(&self, x: usize, y: usize) -> Result<Vec<String>, Box<HackError>> {
+        Ok(self
+            .words(x, y)?
+            .into_iter()
+            .map(|row| {
+                let values: Vec<String> = row.iter().map(|(_, w)| w.to_string()).collect();
+                format!(".data {} {}", SCREEN + row[0].0, values.join(", "))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARROW: &str = "P1\n# an arrow\n3 2\n0 1 0\n1 1 1\n";
+
+    #[test]
+    fn test_pbm() {
+        let plain = Bitmap::decode(ARROW.as_bytes()).unwrap();
+        assert_eq!((plain.width, plain.height), (3, 2));
+        assert_eq!(plain.pixels, vec![false, true, false, true, true, true]);
+        let raw = Bitmap::decode(b"P4 3 2\n\x40\xe0").unwrap();
+        assert!(Bitmap::decode(b"P4 9 1\n\x00\x80").unwrap().pixels[8]);
+        assert_eq!(raw.pixels, plain.pixels);
+        assert_eq!(Bitmap::decode(b"P4 3 2\n\x40").unwrap_err().code, "E019");
+        assert_eq!(Bitmap::decode(b"P1 2 1\n0 2").unwrap_err().code, "E019");
+        assert_eq!(Bitmap::decode(b"GIF89a").unwrap_err().code, "E019");
+        assert_eq!(Bitmap::decode(b"P1\n0 2\n").unwrap_err().code, "E019");
+        assert_eq!(Bitmap::decode(b"P4 3 0\n").unwrap_err().code, "E019");
+        let huge = format!("P4 {} {}\n", usize::MAX / 2, 3);
+        assert_eq!(Bitmap::decode(huge.as_bytes()).unwrap_err().code, "E019");
+    }
+
+    #[test]
+    fn test_png() {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, 3, 2);
+            encoder.set_color(png::ColorType::Rgba);
+            let mut writer = encoder.write_header().unwrap();
+            #[rustfmt::skip]
+            writer.write_image_data(&[
+                255, 255, 255, 255,   0, 0, 0, 255,   0, 0, 0, 0,
+                  0,   0,   0, 255,  40, 0, 0, 255, 200, 200, 200, 255,
+            ]).unwrap();
+        }
+        let bitmap = Bitmap::decode(&bytes).unwrap();
+        assert_eq!(bitmap.pixels, vec![false, true, false, true, true, false]);
+    }
+
+    #[test]
+    fn test_words() {
+        let arrow = Bitmap::decode(ARROW.as_bytes()).unwrap();
+        assert_eq!(
+            arrow.words(0, 0).unwrap(),
+            vec![vec![(0, 0b010)], vec![(32, 0b111)]]
+        );
+        // straddles two words, bit 15 is the rightmost pixel of a word
+        assert_eq!(
+            arrow.words(30, 1).unwrap(),
+            vec![vec![(33, 0x8000), (34, 0)], vec![(65, 0xc000), (66, 1)]]
+        );
+        assert_eq!(arrow.words(510, 0).unwrap_err().code, "E020");
+        assert!(arrow.words(509, 254).is_ok());
+        assert_eq!(arrow.words(usize::MAX, 0).unwrap_err().code, "E020");
+        assert_eq!(arrow.words(0, usize::MAX).unwrap_err().code, "E020");
+    }
+
+    #[test]
+    fn test_code() {
+        let arrow = Bitmap::decode(ARROW.as_bytes()).unwrap();
+        assert_eq!(
+            arrow.code(30, 1).unwrap(),
+            vec![
+                "@32767", "D=!A", "@16417", "M=D", "@16418", "M=0", "@16384", "D=-A", "@16449",
+                "M=D", "@16450", "M=1"
+            ]
+        );
+        assert_eq!(
+            arrow.data(0, 0).unwrap(),
+            vec![".data 16384 2", ".data 16416 7"]
+        );
+        let mut hpu = crate::model::hpu::HPU::new();
+        hpu.parser.verbose = false;
+        assert_eq!(
+            hpu.assemble(&arrow.code(3, 0).unwrap().join("\n"))
+                .unwrap()
+                .len(),
+            8
+        );
+    }
+}
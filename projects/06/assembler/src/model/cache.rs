@@ -0,0 +1,85 @@
+use super::dialect::Dialect;
+use super::hpu::Word;
+use super::isa::Isa;
+use std::collections::HashMap;
+
+/**
+ * Incremental assembly cache
+ *
+ * Keeps the parse of every source line across runs, keyed by the text of
+ * the line once comments are stripped. A line seen in the previous run
+ * is neither expanded, lexed nor parsed again, so after an edit only the
+ * changed lines go through `Lexer::set`. What a line parses to does not
+ * depend on where it stands, see `HPU::parse`: labels and variables are
+ * still resolved by every run, which keeps the output identical to an
+ * uncached one. Lines that fail to parse are not kept, their errors are
+ * reported with the right line number on every run.
+ *
+ * Entries not used by a run are dropped at its end, and the whole cache
+ * is dropped when the instruction set or dialect changes.
+ */
+#[derive(Default)]
+pub struct Cache {
+    config: Option<(Isa, Dialect)>,
+    lines: HashMap<String, Entry>,
+    used: HashMap<String, Entry>, // entries of the run in progress
+    pub hits: usize,
+    pub misses: usize,
+}
+
+/// The lines a source line expands to, each with its parse.
+pub type Entry = Vec<(String, Option<Word>)>;
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache::default()
+    }
+
+    /// Starts a run assembling for `isa` and `dialect`.
+    pub fn begin(&mut self, isa: &Isa, dialect: Dialect) {
+        // a run that failed leaves its entries in `used`
+        let used = std::mem::take(&mut self.used);
+        self.lines.extend(used);
+        if self.config.as_ref() != Some(&(isa.clone(), dialect)) {
+            self.config = Some((isa.clone(), dialect));
+            self.lines.clear();
+        }
+        self.hits = 0;
+        self.misses = 0;
+    }
+
+    /// The entry of `line`, kept for the next run.
+    pub fn get(&mut self, line: &str) -> Option<&Entry> {
+        if !self.used.contains_key(line) {
+            match self.lines.remove(line) {
+                Some(entry) => {
+                    self.used.insert(line.to_owned(), entry);
+                }
+                None => {
+                    self.misses += 1;
+                    return None;
+                }
+            }
+        }
+        self.hits += 1;
+        self.used.get(line)
+    }
+
+    pub fn insert(&mut self, line: String, entry: Entry) {
+        self.used.insert(line, entry);
+    }
+
+    /// Ends the run, forgetting the lines it did not use.
+    pub fn end(&mut self) {
+        self.lines = std::mem::take(&mut self.used);
+    }
+
+    /// Number of lines kept.
+    pub fn len(&self) -> usize {
+        self.lines.len() + self.used.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
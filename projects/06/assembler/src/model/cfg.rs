@@ -0,0 +1,305 @@
+use super::strutil::Strutil;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/**
+ * Control-flow graph
+ *
+ * Blocks start at ROM address 0, at every label, after every jump and at
+ * every address jumped to through `@n`. A jump whose target is not a
+ * constant (`@R13`, `A=M`, `0;JMP`) is an indirect edge.
+ *
+ * The call graph follows the VM translator's calling convention: a block
+ * that loads the address right after its closing `0;JMP` into D
+ * (`@RET_ADDRESS`, `D=A`) is a call site. The callee is the last other
+ * label loaded into D in that block, which covers a shared call routine
+ * taking the function in a register, or else the jump target.
+ */
+pub struct Block {
+    pub start: usize,
+    pub labels: Vec<String>,
+    pub code: Vec<(usize, String)>, // (line number, instruction)
+    pub edges: Vec<Edge>,
+}
+
+pub struct Edge {
+    pub to: Option<usize>, // block index, None if indirect
+    pub jump: String,      // empty for fall-through
+}
+
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    pub labels: BTreeMap<String, usize>,
+}
+
+impl Cfg {
+    /// `code` holds the instructions without labels, `labels` their ROM addresses.
+    pub fn build(
+        code: &[(usize, String)],
+        labels: &BTreeMap<String, usize>,
+        symbols: &BTreeMap<String, i32>,
+    ) -> Cfg {
+        let mut names: HashMap<usize, Vec<String>> = HashMap::new();
+        for (name, addr) in labels {
+            names.entry(*addr).or_default().push(name.clone());
+        }
+        for v in names.values_mut() {
+            v.sort();
+        }
+        // jump target per ROM address, Some(None) if indirect
+        let mut targets: Vec<Option<Option<usize>>> = vec![None; code.len()];
+        let mut a: Option<usize> = None;
+        for (rom, (_, c)) in code.iter().enumerate() {
+            if names.contains_key(&rom) {
+                a = None;
+            }
+            if let Some(v) = c.strip_prefix('@') {
+                a = Cfg::value(v, labels, symbols);
+                continue;
+            }
+            let (dest, _, jump) = Strutil::c_fields(c);
+            if !jump.is_empty() {
+                targets[rom] = Some(a);
+            }
+            if dest.contains('A') {
+                a = None;
+            }
+        }
+        let mut leaders: BTreeSet<usize> = names.keys().copied().collect();
+        leaders.insert(0);
+        for (rom, t) in targets.iter().enumerate() {
+            if let Some(t) = t {
+                leaders.insert(rom + 1);
+                leaders.extend(t);
+            }
+        }
+        let leaders: Vec<usize> = leaders.into_iter().filter(|l| *l < code.len()).collect();
+        let index: HashMap<usize, usize> =
+            leaders.iter().enumerate().map(|(i, l)| (*l, i)).collect();
+        let mut blocks = Vec::new();
+        for (i, start) in leaders.iter().enumerate() {
+            let end = leaders.get(i + 1).copied().unwrap_or(code.len());
+            let mut edges = Vec::new();
+            let fall = Edge {
+                to: index.get(&end).copied(),
+                jump: String::new(),
+            };
+            match targets[end - 1] {
+                Some(t) => {
+                    let jump = Strutil::c_fields(&code[end - 1].1).2.to_owned();
+                    let unconditional = jump == "JMP";
+                    match t {
+                        Some(t) if !index.contains_key(&t) => {}
+                        _ => edges.push(Edge {
+                            to: t.map(|t| index[&t]),
+                            jump,
+                        }),
+                    }
+                    if !unconditional && fall.to.is_some() {
+                        edges.push(fall);
+                    }
+                }
+                None if fall.to.is_some() => edges.push(fall),
+                None => {}
+            }
+            blocks.push(Block {
+                start: *start,
+                labels: names.remove(start).unwrap_or_default(),
+                code: code[*start..end].to_vec(),
+                edges,
+            });
+        }
+        Cfg {
+            blocks,
+            labels: labels.clone(),
+        }
+    }
+
+    /// (caller, callee) pairs, sorted and without duplicates.
+    pub fn calls(&self) -> Vec<(String, String)> {
+        let mut sites: Vec<(usize, String)> = Vec::new();
+        for b in &self.blocks {
+            let end = b.start + b.code.len();
+            match b.code.last() {
+                Some((_, c)) if Strutil::c_fields(c).2 == "JMP" => {}
+                _ => continue,
+            }
+            // labels loaded as addresses: `@X` followed by `D=A`
+            let loaded: Vec<&str> = b
+                .code
+                .windows(2)
+                .filter(|w| w[1].1 == "D=A")
+                .filter_map(|w| w[0].1.strip_prefix('@'))
+                .collect();
+            let returns = |v: &str| match v.parse::<usize>() {
+                Ok(n) => n == end,
+                Err(_) => self.labels.get(v) == Some(&end),
+            };
+            if !loaded.iter().any(|v| returns(v)) {
+                continue;
+            }
+            let target = b
+                .code
+                .iter()
+                .rev()
+                .nth(1)
+                .and_then(|(_, c)| c.strip_prefix('@'));
+            let callee = loaded
+                .iter()
+                .rev()
+                .copied()
+                .find(|v| !returns(v) && self.labels.contains_key(*v))
+                .or_else(|| target.filter(|t| self.labels.contains_key(*t)));
+            if let Some(callee) = callee {
+                sites.push((b.start, callee.to_string()));
+            }
+        }
+        let mut functions: Vec<(usize, &str)> = sites
+            .iter()
+            .map(|(_, f)| (self.labels[f], f.as_str()))
+            .collect();
+        functions.sort_unstable();
+        functions.dedup();
+        let mut ret: Vec<(String, String)> = sites
+            .iter()
+            .map(|(rom, callee)| {
+                let caller = functions
+                    .iter()
+                    .rev()
+                    .find(|(addr, _)| addr <= rom)
+                    .map_or("start", |(_, f)| f);
+                (caller.to_owned(), callee.clone())
+            })
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    pub fn dot(&self, name: &str) -> String {
+        let mut ret = format!("digraph \"{}\" {{\n", Cfg::escape(name));
+        ret.push_str("    node [shape=box fontname=\"monospace\"];\n");
+        for (i, b) in self.blocks.iter().enumerate() {
+            let mut text = format!("{}:", b.start);
+            for l in &b.labels {
+                text.push_str(&format!(" ({})", l));
+            }
+            text.push_str("\\l");
+            for (_, c) in &b.code {
+                text.push_str(&format!("{}\\l", Cfg::escape(c)));
+            }
+            ret.push_str(&format!("    b{} [label=\"{}\"];\n", i, text));
+        }
+        if self
+            .blocks
+            .iter()
+            .any(|b| b.edges.iter().any(|e| e.to.is_none()))
+        {
+            ret.push_str("    indirect [shape=ellipse label=\"indirect\"];\n");
+        }
+        for (i, b) in self.blocks.iter().enumerate() {
+            for e in &b.edges {
+                let to = e.to.map_or("indirect".to_owned(), |t| format!("b{}", t));
+                if e.jump.is_empty() {
+                    ret.push_str(&format!("    b{} -> {};\n", i, to));
+                } else {
+                    ret.push_str(&format!("    b{} -> {} [label=\"{}\"];\n", i, to, e.jump));
+                }
+            }
+        }
+        ret.push_str("}\n");
+        ret
+    }
+
+    pub fn calls_dot(&self, name: &str) -> String {
+        let mut ret = format!("digraph \"{}\" {{\n", Cfg::escape(name));
+        for (caller, callee) in self.calls() {
+            ret.push_str(&format!(
+                "    \"{}\" -> \"{}\";\n",
+                Cfg::escape(&caller),
+                Cfg::escape(&callee)
+            ));
+        }
+        ret.push_str("}\n");
+        ret
+    }
+
+    fn escape(s: &str) -> String {
+        s.replace('\\', "\\\\").replace('"', "\\\"")
+    }
+
+    fn value(
+        v: &str,
+        labels: &BTreeMap<String, usize>,
+        symbols: &BTreeMap<String, i32>,
+    ) -> Option<usize> {
+        v.parse::<usize>()
+            .ok()
+            .or_else(|| labels.get(v).copied())
+            .or_else(|| symbols.get(v).map(|n| *n as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::isa::Isa;
+
+    /// Instructions and label addresses of a source without directives.
+    fn parse(source: &str) -> (Vec<(usize, String)>, BTreeMap<String, usize>) {
+        let mut code = Vec::new();
+        let mut labels = BTreeMap::new();
+        for (num, l) in source.lines().enumerate() {
+            match l.strip_prefix('(') {
+                Some(name) => {
+                    labels.insert(name.trim_end_matches(')').to_owned(), code.len());
+                }
+                None => code.push((num, l.to_owned())),
+            }
+        }
+        (code, labels)
+    }
+
+    fn edges(cfg: &Cfg) -> Vec<(usize, Option<usize>, &str)> {
+        cfg.blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(i, b)| b.edges.iter().map(move |e| (i, e.to, e.jump.as_str())))
+            .collect()
+    }
+
+    #[test]
+    fn test_blocks() {
+        let (code, labels) = parse("@R0\nD=M\n@POS\nD;JGT\n@R13\nA=M\n0;JMP\n(POS)\n@3\n0;JMP\n");
+        let cfg = Cfg::build(&code, &labels, &Isa::hack().symbols);
+        let starts: Vec<usize> = cfg.blocks.iter().map(|b| b.start).collect();
+        assert_eq!(starts, vec![0, 3, 4, 7]);
+        assert_eq!(cfg.blocks[3].labels, vec!["POS".to_owned()]);
+        assert_eq!(
+            edges(&cfg),
+            vec![
+                (0, Some(1), ""),
+                (1, Some(3), "JGT"),
+                (1, Some(2), ""),
+                (2, None, "JMP"),
+                (3, Some(1), "JMP"),
+            ]
+        );
+        let dot = cfg.dot("t");
+        assert!(dot.contains("b3 [label=\"7: (POS)\\l@3\\l0;JMP\\l\"];"));
+        assert!(dot.contains("b2 -> indirect [label=\"JMP\"];"));
+    }
+
+    #[test]
+    fn test_calls() {
+        // direct calls, then a call through a routine taking the function in R14
+        let source = "@RET0\nD=A\n@f\n0;JMP\n(RET0)\n@g\nD=A\n@R14\nM=D\n@RET1\nD=A\n@CALL\n0;JMP\n(RET1)\n(END)\n@END\n0;JMP\n(f)\n@RET2\nD=A\n@g\n0;JMP\n(RET2)\n@R13\nA=M\n0;JMP\n(g)\n@R13\nA=M\n0;JMP\n(CALL)\n@R14\nA=M\n0;JMP\n";
+        let (code, labels) = parse(source);
+        let cfg = Cfg::build(&code, &labels, &Isa::hack().symbols);
+        let pair = |a: &str, b: &str| (a.to_owned(), b.to_owned());
+        assert_eq!(
+            cfg.calls(),
+            vec![pair("f", "g"), pair("start", "f"), pair("start", "g")]
+        );
+        assert!(cfg.calls_dot("t").contains("    \"start\" -> \"f\";\n"));
+    }
+}
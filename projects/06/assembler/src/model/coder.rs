@@ -1,18 +1,22 @@
-use super::base::*;
 use super::error::*;
+use super::isa::Isa;
 use super::parser::*;
-use std::collections::HashMap;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
 
 pub struct Coder {}
 impl Coder {
     pub fn translate_a<'a>(
-        map: &'a mut HashMap<String, usize>,
+        isa: &'a Isa,
+        map: &'a mut BTreeMap<String, usize>,
         varmem: &'a mut usize,
         result: &'a ACmdResult,
     ) -> Result<String, Box<HackError>> {
         match result.value.parse::<i32>() {
             Ok(n) => Ok(format!("0{:015b}", n)),
-            Err(_) => match PREDEFINE_SYMBOLS.get(&result.value) {
+            Err(_) => match isa.symbols.get(&result.value) {
                 Some(n) => Ok(format!("0{:015b}", n)),
                 None => match map.get(&result.value) {
                     Some(n) => Ok(format!("0{:015b}", n)),
@@ -27,30 +31,30 @@ impl Coder {
         }
     }
 
-    pub fn translate_c(result: &CCmdResult) -> Result<String, Box<HackError>> {
-        let mut ret = String::from("111");
+    pub fn translate_c(isa: &Isa, result: &CCmdResult) -> Result<String, Box<HackError>> {
+        let mut ret = isa.prefix.clone();
         match &result.comp {
             Some(d) => {
-                ret.push_str(COMP.get(d).unwrap());
+                ret.push_str(isa.comp.get(d).unwrap());
             }
             None => {
-                ret.push_str("000");
+                ret.push_str(&Isa::none(&isa.comp));
             }
         }
         match &result.dest {
             Some(d) => {
-                ret.push_str(DEST.get(d).unwrap());
+                ret.push_str(isa.dest.get(d).unwrap());
             }
             None => {
-                ret.push_str("000");
+                ret.push_str(&Isa::none(&isa.dest));
             }
         }
         match &result.jump {
             Some(d) => {
-                ret.push_str(JUMP.get(d).unwrap());
+                ret.push_str(isa.jump.get(d).unwrap());
             }
             None => {
-                ret.push_str("000");
+                ret.push_str(&Isa::none(&isa.jump));
             }
         }
         Ok(ret)
@@ -60,28 +64,29 @@ impl Coder {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::error::Error;
     #[test]
     fn test_a_translate() -> Result<(), Box<HackError>> {
-        let mut map: HashMap<String, usize> = HashMap::new();
+        let mut map: BTreeMap<String, usize> = BTreeMap::new();
+        let mut varmem: usize = 16;
+        let isa = Isa::hack();
         map.insert("FOO".into(), 20);
         let result = ACmdResult { value: "R0".into() };
         assert_eq!(
-            Coder::translate_a(&map, &result).unwrap(),
+            Coder::translate_a(&isa, &mut map, &mut varmem, &result).unwrap(),
             "0000000000000000"
         );
         let result = ACmdResult {
             value: "R15".into(),
         };
         assert_eq!(
-            Coder::translate_a(&map, &result).unwrap(),
+            Coder::translate_a(&isa, &mut map, &mut varmem, &result).unwrap(),
             "0000000000001111"
         );
         let result = ACmdResult {
             value: "FOO".into(),
         };
         assert_eq!(
-            Coder::translate_a(&map, &result).unwrap(),
+            Coder::translate_a(&isa, &mut map, &mut varmem, &result).unwrap(),
             "0000000000010100"
         );
         Ok(())
@@ -94,6 +99,9 @@ mod tests {
             comp: Some("M-1".into()),
             jump: Some("JMP".into()),
         };
-        assert_eq!(Coder::translate_c(&result).unwrap(), "10111110010111");
+        assert_eq!(
+            Coder::translate_c(&Isa::hack(), &result).unwrap(),
+            "1111110010011111"
+        );
     }
 }
@@ -0,0 +1,163 @@
+use super::anatomy::Anatomy;
+use super::isa::Isa;
+use super::strutil::Strutil;
+
+/**
+ * Golden-file comparison
+ *
+ * Checks the words of a run against a reference .hack, e.g. the output of
+ * tools/Assembler.sh or the .hack files of projects/05, one address at a
+ * time. Lines of the reference are trimmed, so CRLF files compare equal,
+ * and blank lines are skipped. Each mismatch keeps the source line its word was
+ * assembled from, so the report points at the instruction to fix.
+ */
+pub struct Compare {}
+
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    pub address: usize,
+    pub line: Option<usize>,
+    pub expected: Option<String>, // None past the end of the reference
+    pub actual: Option<String>,   // None past the end of the program
+}
+
+/// Mismatches listed in full before the report summarizes the rest.
+pub const REPORT_LIMIT: usize = 10;
+
+impl Compare {
+    /// Every address where `rom` and the words of `expected` differ.
+    pub fn diff(expected: &str, rom: &[(usize, String)]) -> Vec<Mismatch> {
+        let expected: Vec<&str> = expected
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty())
+            .collect();
+        (0..expected.len().max(rom.len()))
+            .filter_map(|address| {
+                let want = expected.get(address).copied();
+                let got = rom.get(address);
+                if want.is_some() && want == got.map(|(_, w)| w.as_str()) {
+                    return None;
+                }
+                Some(Mismatch {
+                    address,
+                    line: got.map(|(num, _)| *num),
+                    expected: want.map(|w| w.to_owned()),
+                    actual: got.map(|(_, w)| w.clone()),
+                })
+            })
+            .collect()
+    }
+
+    /// The first `REPORT_LIMIT` mismatches with the line of `source` they
+    /// come from and both words decoded.
+    pub fn report(mismatches: &[Mismatch], source: &str, isa: &Isa) -> String {
+        let lines: Vec<&str> = source.lines().collect();
+        let word = |w: &Option<String>| match w {
+            Some(w) => format!("{:<16}  {}", w, Anatomy::disassemble(w, isa)),
+            None => "(missing)".to_owned(),
+        };
+        let mut ret = String::new();
+        for m in mismatches.iter().take(REPORT_LIMIT) {
+            match m.line {
+                Some(num) => {
+                    let line = lines.get(num).map_or("", |l| Strutil::split_comment(l).0);
+                    ret.push_str(&format!("ROM[{}] from [{}]: {}\n", m.address, num, line));
+                }
+                None => ret.push_str(&format!("ROM[{}] past the end of the program\n", m.address)),
+            }
+            ret.push_str(&format!("  expected {}\n", word(&m.expected)));
+            ret.push_str(&format!("  actual   {}\n", word(&m.actual)));
+        }
+        if mismatches.len() > REPORT_LIMIT {
+            ret.push_str(&format!(
+                "... and {} more\n",
+                mismatches.len() - REPORT_LIMIT
+            ));
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::assembler::create_assembler;
+    use crate::model::dialect::Dialect;
+    use std::path::Path;
+
+    fn rom(words: &[&str]) -> Vec<(usize, String)> {
+        words
+            .iter()
+            .enumerate()
+            .map(|(num, w)| (num, w.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let words = ["0000000000000010", "1110110000010000"];
+        assert!(
+            Compare::diff("0000000000000010\r\n1110110000010000\r\n\r\n", &rom(&words)).is_empty()
+        );
+        let mismatches = Compare::diff(
+            "0000000000000011\n1110110000010000\n1110001100001000\n",
+            &rom(&words),
+        );
+        assert_eq!(
+            mismatches,
+            vec![
+                Mismatch {
+                    address: 0,
+                    line: Some(0),
+                    expected: Some("0000000000000011".into()),
+                    actual: Some("0000000000000010".into()),
+                },
+                Mismatch {
+                    address: 2,
+                    line: None,
+                    expected: Some("1110001100001000".into()),
+                    actual: None,
+                },
+            ]
+        );
+        let text = Compare::report(&mismatches, "@2 // two\nD=A\n", &Isa::hack());
+        assert_eq!(
+            text,
+            "ROM[0] from [0]: @2\n  expected 0000000000000011  @3\n  actual   0000000000000010  @2\n\
+             ROM[2] past the end of the program\n  expected 1110001100001000  M=D\n  actual   (missing)\n"
+        );
+    }
+
+    #[test]
+    fn test_report_limit() {
+        let words = vec!["1110101010000111"; REPORT_LIMIT + 3];
+        let mismatches = Compare::diff("", &rom(&words));
+        assert_eq!(mismatches.len(), REPORT_LIMIT + 3);
+        let text = Compare::report(&mismatches, "", &Isa::hack());
+        assert!(text.ends_with("... and 3 more\n"));
+        assert_eq!(
+            text.lines().filter(|l| l.starts_with("ROM[")).count(),
+            REPORT_LIMIT
+        );
+    }
+
+    #[test]
+    fn test_projects() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        for (asm, hack) in &[
+            ("max/Max.asm", "../05/Max.hack"),
+            ("rect/Rect.asm", "../05/Rect.hack"),
+        ] {
+            let out =
+                std::env::temp_dir().join(format!("hack-compare-{}.hack", std::process::id()));
+            let mut assembler = create_assembler(&root.join(asm), Isa::hack(), Dialect::Strict);
+            assembler.set_verbose(false);
+            assembler.set_output(&out);
+            assembler.run().unwrap();
+            let _ = std::fs::remove_file(&out);
+            let expected = std::fs::read_to_string(root.join(hack)).unwrap();
+            assert_eq!(Compare::diff(&expected, assembler.rom()), vec![], "{}", asm);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use super::cfg::Cfg;
+use super::strutil::Strutil;
+use std::collections::{BTreeMap, BTreeSet};
+
+/**
+ * Definite assignment
+ *
+ * Tracks which of D, A and the RAM variables are assigned on every path
+ * into each block of the control-flow graph and reports reads of the
+ * others. Variables are the symbols `Coder::translate_a` would allocate,
+ * `uninit` adds predefined registers a test script leaves undefined, such
+ * as R2 for Mult. Blocks without a predecessor, like return addresses
+ * reached through an indirect jump, are not analyzed.
+ */
+#[derive(Debug, PartialEq)]
+pub struct Read {
+    pub line_num: usize,
+    pub line: String,
+    pub name: String,
+}
+
+// None stands for every name assigned, the state of unreached code
+type State = Option<BTreeSet<String>>;
+
+pub struct Dataflow<'a> {
+    cfg: &'a Cfg,
+    symbols: &'a BTreeMap<String, i32>,
+    uninit: Vec<(String, i64)>,
+}
+
+impl<'a> Dataflow<'a> {
+    pub fn uninitialized(
+        cfg: &'a Cfg,
+        symbols: &'a BTreeMap<String, i32>,
+        uninit: &[String],
+    ) -> Vec<Read> {
+        let flow = Dataflow {
+            cfg,
+            symbols,
+            uninit: uninit
+                .iter()
+                .filter_map(|u| Dataflow::value(u, symbols).map(|v| (u.clone(), v)))
+                .collect(),
+        };
+        let mut preds: Vec<Vec<usize>> = vec![Vec::new(); cfg.blocks.len()];
+        for (i, b) in cfg.blocks.iter().enumerate() {
+            for to in b.edges.iter().filter_map(|e| e.to) {
+                preds[to].push(i);
+            }
+        }
+        let mut outs: Vec<State> = vec![None; cfg.blocks.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..cfg.blocks.len() {
+                let out = flow.transfer(i, flow.entry(i, &preds, &outs), &mut Vec::new());
+                if out != outs[i] {
+                    outs[i] = out;
+                    changed = true;
+                }
+            }
+        }
+        let mut ret = Vec::new();
+        for i in 0..cfg.blocks.len() {
+            flow.transfer(i, flow.entry(i, &preds, &outs), &mut ret);
+        }
+        ret
+    }
+
+    fn entry(&self, i: usize, preds: &[Vec<usize>], outs: &[State]) -> State {
+        let mut ret: State = if i == 0 { Some(BTreeSet::new()) } else { None };
+        for p in &preds[i] {
+            ret = match (ret, &outs[*p]) {
+                (None, s) => s.clone(),
+                (s, None) => s,
+                (Some(a), Some(b)) => Some(a.intersection(b).cloned().collect()),
+            };
+        }
+        ret
+    }
+
+    /// State at the end of block `i`, pushing the reads of unassigned names.
+    fn transfer(&self, i: usize, state: State, reads: &mut Vec<Read>) -> State {
+        let mut state = state?;
+        let mut addressed: Option<String> = None;
+        for (num, code) in &self.cfg.blocks[i].code {
+            let mut read = |name: &str, state: &BTreeSet<String>| {
+                if !state.contains(name) {
+                    reads.push(Read {
+                        line_num: *num,
+                        line: code.clone(),
+                        name: name.to_owned(),
+                    });
+                }
+            };
+            if let Some(v) = code.strip_prefix('@') {
+                addressed = self.tracked(v);
+                state.insert("A".into());
+                continue;
+            }
+            let (dest, comp, _) = Strutil::c_fields(code);
+            for r in ["D", "A"].iter() {
+                if comp.contains(r) {
+                    read(r, &state);
+                }
+            }
+            if comp.contains('M') {
+                read("A", &state);
+                if let Some(v) = &addressed {
+                    read(v, &state);
+                }
+            }
+            if dest.contains('M') {
+                if let Some(v) = &addressed {
+                    state.insert(v.clone());
+                }
+            }
+            if dest.contains('D') {
+                state.insert("D".into());
+            }
+            if dest.contains('A') {
+                state.insert("A".into());
+                addressed = None;
+            }
+        }
+        Some(state)
+    }
+
+    /// Name tracked for the RAM word `@v` addresses, if any.
+    fn tracked(&self, v: &str) -> Option<String> {
+        match Dataflow::value(v, self.symbols) {
+            Some(n) => self
+                .uninit
+                .iter()
+                .find(|(_, u)| *u == n)
+                .map(|(u, _)| u.clone()),
+            None if !self.cfg.labels.contains_key(v) => Some(v.to_owned()),
+            None => None,
+        }
+    }
+
+    fn value(v: &str, symbols: &BTreeMap<String, i32>) -> Option<i64> {
+        v.parse::<i64>()
+            .ok()
+            .or_else(|| symbols.get(v).map(|n| *n as i64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::isa::Isa;
+
+    fn names(source: &str, uninit: &[&str]) -> Vec<(usize, String)> {
+        let mut code = Vec::new();
+        let mut labels = BTreeMap::new();
+        for (num, l) in source.lines().enumerate() {
+            match l.strip_prefix('(') {
+                Some(name) => {
+                    labels.insert(name.trim_end_matches(')').to_owned(), code.len());
+                }
+                None => code.push((num, l.to_owned())),
+            }
+        }
+        let symbols = Isa::hack().symbols;
+        let cfg = Cfg::build(&code, &labels, &symbols);
+        let uninit: Vec<String> = uninit.iter().map(|u| u.to_string()).collect();
+        Dataflow::uninitialized(&cfg, &symbols, &uninit)
+            .into_iter()
+            .map(|r| (r.line_num, r.name))
+            .collect()
+    }
+
+    #[test]
+    fn test_registers() {
+        assert_eq!(names("D=D+1\n", &[]), vec![(0, "D".into())]);
+        assert_eq!(names("D=0\nD=D+1\n", &[]), vec![]);
+        assert_eq!(names("M=1\n", &[]), vec![]);
+        assert_eq!(names("D=M\n", &[]), vec![(0, "A".into())]);
+    }
+
+    #[test]
+    fn test_paths() {
+        // sum is assigned on one branch only
+        let source = "@R0\nD=M\n@SKIP\nD;JEQ\n@sum\nM=0\n(SKIP)\n@sum\nD=M\n";
+        assert_eq!(names(source, &[]), vec![(8, "sum".into())]);
+        let source = "@sum\nM=0\n@R0\nD=M\n@SKIP\nD;JEQ\n@sum\nM=D\n(SKIP)\n@sum\nD=M\n";
+        assert_eq!(names(source, &[]), vec![]);
+        // the loop back edge does not hide the first iteration
+        let source = "(LOOP)\n@i\nM=M+1\n@LOOP\n0;JMP\n";
+        assert_eq!(names(source, &[]), vec![(2, "i".into())]);
+    }
+
+    #[test]
+    fn test_uninit_registers() {
+        let source = "@R0\nD=M\n@2\nM=D+M\n";
+        assert_eq!(names(source, &[]), vec![]);
+        assert_eq!(names(source, &["R2"]), vec![(3, "R2".into())]);
+        assert_eq!(
+            names("@R2\nM=0\n@2\nM=D+M\n", &["R2"]),
+            vec![(3, "D".into())]
+        );
+    }
+}
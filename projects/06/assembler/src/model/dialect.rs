@@ -0,0 +1,139 @@
+use super::base::*;
+use super::isa::Isa;
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/**
+ * C-instruction spelling
+ *
+ * Other Hack toolchains accept commuted operands (A+D, M&D) and any
+ * order of destination registers (DM, ADM). The strict dialect rejects
+ * them and suggests the canonical spelling, the relaxed one rewrites the
+ * tokens to the canonical spelling before they are parsed.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+    Strict,
+    Relaxed,
+}
+
+impl core::str::FromStr for Dialect {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Dialect, String> {
+        match s {
+            "strict" => Ok(Dialect::Strict),
+            "relaxed" => Ok(Dialect::Relaxed),
+            _ => Err(format!("Unknown dialect {}, expected strict or relaxed", s)),
+        }
+    }
+}
+
+impl Dialect {
+    /// Canonical dest mnemonic naming the same registers as `s`.
+    pub fn canonical_dest(s: &str, table: &BTreeMap<String, String>) -> Option<String> {
+        let sorted = |s: &str| {
+            let mut v: Vec<char> = s.chars().collect();
+            v.sort_unstable();
+            v
+        };
+        let key = sorted(s);
+        table.keys().find(|k| sorted(k) == key).cloned()
+    }
+
+    /// Canonical comp mnemonic for a commuted `x+y`, `x&y` or `x|y`.
+    pub fn canonical_comp(s: &str, table: &BTreeMap<String, String>) -> Option<String> {
+        if table.contains_key(s) {
+            return Some(s.to_owned());
+        }
+        let i = s
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| "+&|".contains(*c))?
+            .0;
+        let swapped = format!("{}{}{}", &s[i + 1..], &s[i..i + 1], &s[..i]);
+        if table.contains_key(&swapped) {
+            Some(swapped)
+        } else {
+            None
+        }
+    }
+
+    /// Suggestion for a mnemonic missing from `table`, named as in the parser.
+    pub fn suggest(table: &str, s: &str, isa: &Isa) -> Option<String> {
+        match table {
+            "dest" => Dialect::canonical_dest(s, &isa.dest),
+            "comp" => Dialect::canonical_comp(s, &isa.comp),
+            _ => None,
+        }
+    }
+
+    /// Rewrites the dest and comp tokens of a C-command to their canonical spelling.
+    pub fn normalize(tokens: &mut [Token], isa: &Isa) {
+        for i in 0..tokens.len() {
+            let canonical = if i + 1 < tokens.len() && tokens[i + 1].token_type == TOKENTYPE::EQUAL
+            {
+                Dialect::canonical_dest(&tokens[i].repr, &isa.dest)
+            } else if tokens[i].token_type == TOKENTYPE::EQUAL
+                || tokens[i].token_type == TOKENTYPE::SEMICOLON
+                || (i > 0 && tokens[i - 1].token_type == TOKENTYPE::SEMICOLON)
+            {
+                None
+            } else {
+                Dialect::canonical_comp(&tokens[i].repr, &isa.comp)
+            };
+            if let Some(c) = canonical {
+                tokens[i].repr = c;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::lexer::Lexer;
+
+    #[test]
+    fn test_canonical() {
+        let isa = Isa::hack();
+        assert_eq!(
+            Dialect::canonical_comp("A+D", &isa.comp),
+            Some("D+A".into())
+        );
+        assert_eq!(
+            Dialect::canonical_comp("M&D", &isa.comp),
+            Some("D&M".into())
+        );
+        assert_eq!(
+            Dialect::canonical_comp("1+D", &isa.comp),
+            Some("D+1".into())
+        );
+        assert_eq!(
+            Dialect::canonical_comp("D-A", &isa.comp),
+            Some("D-A".into())
+        );
+        assert_eq!(Dialect::canonical_comp("-1", &isa.comp), Some("-1".into()));
+        assert_eq!(Dialect::canonical_comp("M-A", &isa.comp), None);
+        assert_eq!(Dialect::canonical_dest("DM", &isa.dest), Some("MD".into()));
+        assert_eq!(
+            Dialect::canonical_dest("ADM", &isa.dest),
+            Some("AMD".into())
+        );
+        assert_eq!(Dialect::canonical_dest("MM", &isa.dest), None);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let isa = Isa::hack();
+        let mut lexer = Lexer::new();
+        lexer.set("DM=M+D;JMP").unwrap();
+        Dialect::normalize(&mut lexer.tokens, &isa);
+        let reprs: Vec<&str> = lexer.tokens.iter().map(|t| t.repr.as_str()).collect();
+        assert_eq!(reprs, vec!["MD", "=", "D+M", ";", "JMP"]);
+        assert_eq!("relaxed".parse::<Dialect>(), Ok(Dialect::Relaxed));
+        assert!("loose".parse::<Dialect>().is_err());
+    }
+}
@@ -0,0 +1,340 @@
+use super::error::*;
+use crate::hack_report_line;
+use std::collections::BTreeMap;
+
+/**
+ * Data directives
+ *
+ * Hack cannot load data from ROM, so every constant has to be written
+ * into RAM by code. The directives below expand into that code before
+ * the passes see the line:
+ *
+ * .data ADDR v0, v1, ...   RAM[ADDR+i] = vi, 4 words per value
+ * .string ADDR "TEXT"      RAM[ADDR+i] = TEXT[i], zero terminated
+ * .table NAME v0, v1, ...  lookup routine NAME: D = vD, 4 words per value + 9
+ *
+ * ADDR is a number or a predefined symbol of the ISA. A table routine expects the
+ * index in D and the return address in R15, and clobbers R14.
+ *
+ * Check directives emit no code:
+ *
+ * .assert EXPR, "message"  fails the build if EXPR is 0 once all symbols are known
+ * .error "message"         fails the build
+ * .warning "message"       reports the message and carries on
+ * .export NAME, ...        makes labels visible to other objects, see `Object`
+ */
+pub struct Directive {}
+
+#[derive(Debug, PartialEq)]
+pub enum Check {
+    Assert { expr: String, message: String },
+    Error(String),
+    Warning(String),
+    Export(Vec<String>),
+}
+
+impl Directive {
+    pub fn is_directive(s: &str) -> bool {
+        s.starts_with('.')
+    }
+
+    /// Number of ROM words an expansion occupies, labels excluded.
+    pub fn size(lines: &[String]) -> usize {
+        lines.iter().filter(|l| !l.starts_with('(')).count()
+    }
+
+    pub fn is_check(s: &str) -> bool {
+        let (name, _) = Directive::split_word(s);
+        [".assert", ".error", ".warning", ".export"].contains(&name)
+    }
+
+    pub fn check(num: usize, line: &str) -> Result<Check, Box<HackError>> {
+        let (name, rest) = Directive::split_word(line);
+        match name {
+            ".assert" => {
+                // the message starts at the first quote, it may hold commas
+                let comma = rest.find('"').and_then(|q| rest[..q].rfind(','));
+                let (expr, message) = match comma {
+                    Some(i) if rest[i + 1..].trim().starts_with('"') => (
+                        rest[..i].trim(),
+                        Directive::quoted(num, line, &rest[i + 1..])?,
+                    ),
+                    _ => (rest, rest.to_owned()),
+                };
+                if expr.is_empty() {
+                    hack_report_line!(num, line, "E011", ".assert expects an expression")
+                }
+                Ok(Check::Assert {
+                    expr: expr.to_owned(),
+                    message,
+                })
+            }
+            ".error" => Ok(Check::Error(Directive::quoted(num, line, rest)?)),
+            ".export" => {
+                let names: Vec<String> = rest
+                    .split(',')
+                    .map(|n| n.trim().to_owned())
+                    .filter(|n| !n.is_empty())
+                    .collect();
+                if names.is_empty() {
+                    hack_report_line!(num, line, "E012", ".export expects a label")
+                }
+                Ok(Check::Export(names))
+            }
+            _ => Ok(Check::Warning(Directive::quoted(num, line, rest)?)),
+        }
+    }
+
+    pub fn expand(
+        num: usize,
+        line: &str,
+        symbols: &BTreeMap<String, i32>,
+    ) -> Result<Vec<String>, Box<HackError>> {
+        if Directive::is_check(line) {
+            Directive::check(num, line)?;
+            return Ok(Vec::new());
+        }
+        let (name, rest) = Directive::split_word(line);
+        let (target, rest) = Directive::split_word(rest);
+        if target.is_empty() {
+            hack_report_line!(num, line, "E012", format!("{} expects an operand", name))
+        }
+        match name {
+            ".data" => {
+                let addr = Directive::address(num, line, target, symbols)?;
+                let values = Directive::values(num, line, rest)?;
+                Directive::store(num, line, addr, &values)
+            }
+            ".string" => {
+                let addr = Directive::address(num, line, target, symbols)?;
+                let text = Directive::quoted(num, line, rest)?;
+                if let Some(c) = text.chars().find(|c| *c as u32 > 0xffff) {
+                    hack_report_line!(
+                        num,
+                        line,
+                        "E013",
+                        format!("{} (U+{:X}) is not a 16-bit value", c, c as u32)
+                    )
+                }
+                let mut values: Vec<i32> = text.chars().map(|c| c as i32).collect();
+                values.push(0);
+                Directive::store(num, line, addr, &values)
+            }
+            ".table" => {
+                let values = Directive::values(num, line, rest)?;
+                Ok(Directive::table(target, &values))
+            }
+            _ => hack_report_line!(num, line, "E012", format!("Unknown directive {}", name)),
+        }
+    }
+
+    fn split_word(s: &str) -> (&str, &str) {
+        let s = s.trim();
+        match s.find(char::is_whitespace) {
+            Some(i) => (&s[..i], s[i..].trim()),
+            None => (s, ""),
+        }
+    }
+
+    fn quoted(num: usize, line: &str, s: &str) -> Result<String, Box<HackError>> {
+        let s = s.trim();
+        if s.len() < 2 || !s.starts_with('"') || !s.ends_with('"') {
+            hack_report_line!(num, line, "E012", "Expect a quoted string")
+        }
+        Ok(s[1..s.len() - 1].to_owned())
+    }
+
+    fn address(
+        num: usize,
+        line: &str,
+        s: &str,
+        symbols: &BTreeMap<String, i32>,
+    ) -> Result<i32, Box<HackError>> {
+        match s.parse::<i32>() {
+            Ok(n) => Ok(n),
+            Err(_) => match symbols.get(s) {
+                Some(n) => Ok(*n),
+                None => hack_report_line!(
+                    num,
+                    line,
+                    "E012",
+                    format!("{} is neither a number nor a predefined symbol", s)
+                ),
+            },
+        }
+    }
+
+    fn values(num: usize, line: &str, s: &str) -> Result<Vec<i32>, Box<HackError>> {
+        let mut ret = Vec::new();
+        for v in s.split(',') {
+            match v.trim().parse::<i32>() {
+                Ok(n) if (-32768..=65535).contains(&n) => ret.push(n),
+                _ => hack_report_line!(
+                    num,
+                    line,
+                    "E013",
+                    format!("{} is not a 16-bit value", v.trim())
+                ),
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Two instructions that leave `value` in D.
+    fn load_d(value: i32) -> Vec<String> {
+        let w = value as i16;
+        if w >= 0 {
+            vec![format!("@{}", w), "D=A".into()]
+        } else if w == i16::MIN {
+            vec!["@32767".into(), "D=!A".into()]
+        } else {
+            vec![format!("@{}", -w), "D=-A".into()]
+        }
+    }
+
+    fn store(
+        num: usize,
+        line: &str,
+        addr: i32,
+        values: &[i32],
+    ) -> Result<Vec<String>, Box<HackError>> {
+        if addr < 0 || addr + values.len() as i32 > 32768 {
+            hack_report_line!(num, line, "E014", "Data does not fit in the addressable RAM")
+        }
+        let mut ret = Vec::new();
+        for (i, v) in values.iter().enumerate() {
+            ret.extend(Directive::load_d(*v));
+            ret.push(format!("@{}", addr + i as i32));
+            ret.push("M=D".into());
+        }
+        Ok(ret)
+    }
+
+    fn table(name: &str, values: &[i32]) -> Vec<String> {
+        let entries = format!("__{}_ENTRIES", name);
+        let ret_label = format!("__{}_RETURN", name);
+        let mut ret: Vec<String> = vec![
+            format!("({})", name),
+            // every entry is 4 words long: jump to ENTRIES + 4 * D
+            "@R14".into(),
+            "M=D".into(),
+            "D=D+M".into(),
+            "M=D".into(),
+            "D=D+M".into(),
+            format!("@{}", entries),
+            "A=D+A".into(),
+            "0;JMP".into(),
+            format!("({})", entries),
+        ];
+        // a label per entry tells the optimizer it is reachable; the last
+        // entry falls through to the return
+        for (i, v) in values.iter().enumerate() {
+            if i > 0 {
+                ret.push(format!("(__{}_{})", name, i));
+            }
+            ret.extend(Directive::load_d(*v));
+            if i + 1 < values.len() {
+                ret.push(format!("@{}", ret_label));
+                ret.push("0;JMP".into());
+            }
+        }
+        ret.push(format!("({})", ret_label));
+        ret.push("@R15".into());
+        ret.push("A=M".into());
+        ret.push("0;JMP".into());
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::isa::Isa;
+
+    #[test]
+    fn test_data() {
+        let out = Directive::expand(0, ".data SCREEN 1, -2, -32768", &Isa::hack().symbols).unwrap();
+        assert_eq!(
+            out,
+            vec![
+                "@1", "D=A", "@16384", "M=D", "@2", "D=-A", "@16385", "M=D", "@32767", "D=!A",
+                "@16386", "M=D"
+            ]
+        );
+        assert_eq!(Directive::size(&out), 12);
+        assert!(Directive::expand(0, ".data FOO 1", &Isa::hack().symbols).is_err());
+        assert!(Directive::expand(0, ".data 32767 1, 2", &Isa::hack().symbols).is_err());
+        assert!(Directive::expand(0, ".data 100 70000", &Isa::hack().symbols).is_err());
+    }
+
+    #[test]
+    fn test_string() {
+        let out = Directive::expand(0, ".string 100 \"A B\"", &Isa::hack().symbols).unwrap();
+        assert_eq!(out[0], "@65");
+        assert_eq!(out[4], "@32");
+        assert_eq!(out[10], "@102");
+        assert_eq!(out[12], "@0");
+        assert_eq!(Directive::size(&out), 16);
+        assert!(Directive::expand(0, ".string 100 HELLO", &Isa::hack().symbols).is_err());
+        let euro = Directive::expand(0, ".string 100 \"€\"", &Isa::hack().symbols).unwrap();
+        assert_eq!(euro[0], "@8364");
+        let e = Directive::expand(0, ".string 100 \"a😀\"", &Isa::hack().symbols).unwrap_err();
+        assert_eq!(e.code, "E013");
+    }
+
+    #[test]
+    fn test_table() {
+        let out = Directive::expand(0, ".table SQUARE 0, 1, 4, 9", &Isa::hack().symbols).unwrap();
+        assert_eq!(out[0], "(SQUARE)");
+        assert_eq!(out[9], "(__SQUARE_ENTRIES)");
+        assert_eq!(out[14], "(__SQUARE_1)");
+        assert_eq!(Directive::size(&out), 8 + 4 * 4 - 2 + 3);
+        assert!(Directive::expand(0, ".bogus 1", &Isa::hack().symbols).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(
+            Directive::check(
+                0,
+                ".assert BUFFER+64 <= SCREEN, \"buffer overlaps the screen\""
+            )
+            .unwrap(),
+            Check::Assert {
+                expr: "BUFFER+64 <= SCREEN".into(),
+                message: "buffer overlaps the screen".into()
+            }
+        );
+        assert_eq!(
+            Directive::check(0, ".assert END < 32768").unwrap(),
+            Check::Assert {
+                expr: "END < 32768".into(),
+                message: "END < 32768".into()
+            }
+        );
+        assert_eq!(
+            Directive::check(0, ".assert END < 100, \"too big, fix it\"").unwrap(),
+            Check::Assert {
+                expr: "END < 100".into(),
+                message: "too big, fix it".into()
+            }
+        );
+        assert_eq!(
+            Directive::check(0, ".warning \"slow path\"").unwrap(),
+            Check::Warning("slow path".into())
+        );
+        assert_eq!(
+            Directive::check(0, ".export MULT, DIV").unwrap(),
+            Check::Export(vec!["MULT".into(), "DIV".into()])
+        );
+        assert!(Directive::check(0, ".export").is_err());
+        assert!(Directive::check(0, ".error oops").is_err());
+        assert!(Directive::check(0, ".assert").is_err());
+        assert_eq!(
+            Directive::expand(0, ".error \"oops\"", &Isa::hack().symbols)
+                .unwrap()
+                .len(),
+            0
+        );
+    }
+}
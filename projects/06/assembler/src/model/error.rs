@@ -1,30 +1,34 @@
+use alloc::string::String;
+
 #[derive(Debug)]
 pub struct HackError {
     pub source_line_num: Option<usize>,
     pub source_line: Option<String>,
+    pub code: &'static str, // stable id, see `hack explain`
     pub comment: String,
 }
 
-impl std::fmt::Display for HackError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        writeln!(
-            f,
-            "[{}]: {}: {}",
-            self.source_line_num.as_ref().unwrap(),
-            self.source_line.as_ref().unwrap(),
-            self.comment
-        )
+impl core::fmt::Display for HackError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match (&self.source_line_num, &self.source_line) {
+            (Some(num), Some(line)) => {
+                writeln!(f, "[{}]: {}: {} {}", num, line, self.code, self.comment)
+            }
+            _ => writeln!(f, "{} {}", self.code, self.comment),
+        }
     }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for HackError {}
 
 #[macro_export]
 macro_rules! hack_report {
-    ($parg:ident, $comment: expr) => {{
+    ($parg:ident, $code: expr, $comment: expr) => {{
         return Err(Box::new(HackError {
             source_line_num: Some($parg.line_num()),
             source_line:     Some($parg.content.clone()),
+            code:            $code,
             comment:         $comment.to_string(),
         }
     ));
@@ -33,13 +37,26 @@ macro_rules! hack_report {
 
 #[macro_export]
 macro_rules! hack_report_less {
-    ($comment: expr) => {{
+    ($code: expr, $comment: expr) => {{
         return Err(Box::new(
             HackError {
                 source_line_num: None,
                 source_line:     None,
+                code:            $code,
                 comment:         $comment.to_string(),
             }
         ));
     }};
 }
+
+#[macro_export]
+macro_rules! hack_report_line {
+    ($num: expr, $line: expr, $code: expr, $comment: expr) => {{
+        return Err(Box::new(HackError {
+            source_line_num: Some($num),
+            source_line:     Some($line.to_string()),
+            code:            $code,
+            comment:         $comment.to_string(),
+        }));
+    }};
+}
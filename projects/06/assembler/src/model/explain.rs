@@ -0,0 +1,338 @@
+/**
+ * Error index
+ *
+ * Every `HackError` carries one of these codes. `hack explain E004`
+ * prints the summary followed by the long explanation, which shows an
+ * incorrect and a corrected program wherever the error comes from source.
+ */
+pub struct Explain {}
+
+pub const CODES: [(&str, &str, &str); 20] = [
+    (
+        "E001",
+        "empty line handed to the lexer",
+        r#"The lexer was asked to tokenize a line with nothing on it. The
+assembler drops blank lines and comments before lexing, so this points at
+a bug in the caller rather than in the program. Please report it along with
+the program that triggers it."#,
+    ),
+    (
+        "E002",
+        "unexpected token",
+        r#"The parser expected a particular token, such as the `)` closing a
+label or a jump mnemonic after `;`, and found another one.
+
+Incorrect:
+
+    (LOOP X)
+    M=D;1
+
+Correct:
+
+    (LOOP_X)
+    M=D;JGT"#,
+    ),
+    (
+        "E003",
+        "dest, comp or jump not in the instruction set",
+        r#"A C-instruction names a destination, computation or jump that is
+missing from the DEST, COMP or JUMP table of the instruction set. When a
+close spelling exists it is suggested. With `--dialect relaxed` commuted
+spellings such as `M+D` are rewritten to the canonical `D+M`.
+
+Incorrect:
+
+    D=Q
+    0;JMPP
+
+Correct:
+
+    D=M
+    0;JMP"#,
+    ),
+    (
+        "E004",
+        "illegal A-instruction",
+        r#"An A-instruction takes a single constant from 0 to 32767 or a symbol.
+Expressions and negative numbers cannot be loaded by `@`, compute them with
+a C-instruction instead. Symbols starting with `std.` belong to the
+standard library, of which a program may load only the routine entries,
+such as `@std.mult`.
+
+Incorrect:
+
+    @-1
+    @1+2
+    @32768
+    @std.mult.ret
+
+Correct:
+
+    D=-1
+    @3
+    @32767
+    D=!A
+    @std.mult"#,
+    ),
+    (
+        "E005",
+        "predefined symbol used as a label",
+        r#"Labels share one namespace with the predefined symbols (SP, LCL,
+ARG, THIS, THAT, R0-R15, SCREEN and KBD). Defining one of them as a label
+would silently change what every `@SP` means, so it is rejected.
+
+Incorrect:
+
+    (SP)
+    0;JMP
+
+Correct:
+
+    (START)
+    0;JMP"#,
+    ),
+    (
+        "E006",
+        "malformed label",
+        r#"A label declaration is a symbol between parentheses. The symbol may
+not be empty and may not be a number.
+
+Incorrect:
+
+    ()
+    (1)
+
+Correct:
+
+    (ONE)"#,
+    ),
+    (
+        "E007",
+        "program does not fit in ROM",
+        r#"The Hack ROM holds 32768 words and the program needs more. Run with
+`--stats` to see the instruction counts, and `-O` to drop redundant loads,
+jumps to the next instruction and unreachable code."#,
+    ),
+    (
+        "E008",
+        "could not read or write a file",
+        r#"Reading the program, an include or an --isa description, or writing
+the output, failed. The message carries the error from the operating
+system. Check that the path exists and that the output directory is
+writable."#,
+    ),
+    (
+        "E009",
+        "the program raised an .error directive",
+        r#"The source contains an `.error` directive, which stops assembly with
+its message. The assembler has no conditionals, so the directive always
+fires: it marks code that must not be built as it stands, such as an
+unfinished port. Read the message and fix what it points at, then remove the
+directive, or turn it into a `.warning` to build anyway.
+
+Incorrect:
+
+    .error "not ported to this board yet"
+
+Correct:
+
+    .warning "not ported to this board yet""#,
+    ),
+    (
+        "E010",
+        "assertion failed",
+        r#"An `.assert` expression evaluated to 0 once every label was known.
+Assertions typically guard the layout of a program, such as a table having
+to start at a given address.
+
+Fix the layout rather than the assertion: move the code that pushed the
+label away from where it has to be.
+
+Incorrect, the setup code puts SQUARES at 4:
+
+    @1
+    D=A
+    @START
+    0;JMP
+    .table SQUARES 0, 1, 4, 9
+    (START)
+    .assert SQUARES == 2, "SQUARES must follow the jump at 0"
+
+Correct, the setup code moved after the table:
+
+    @START
+    0;JMP
+    .table SQUARES 0, 1, 4, 9
+    (START)
+    @1
+    D=A
+    .assert SQUARES == 2, "SQUARES must follow the jump at 0""#,
+    ),
+    (
+        "E011",
+        "invalid .assert expression",
+        r#"The expression of an `.assert` is missing or could not be evaluated,
+for instance because it names an undefined symbol or is not well formed.
+
+Incorrect:
+
+    .assert
+    .assert LOOP <
+
+Correct:
+
+    .assert LOOP < 100"#,
+    ),
+    (
+        "E012",
+        "malformed directive",
+        r#"The directive is unknown or its operands are wrong: a missing
+operand, a string without quotes, or an address that is neither a number
+nor a predefined symbol.
+
+Incorrect:
+
+    .string 100 HELLO
+    .data FOO 1
+
+Correct:
+
+    .string 100 "HELLO"
+    .data SCREEN 1"#,
+    ),
+    (
+        "E013",
+        "value does not fit in 16 bits",
+        r#"A value given to a data directive must fit in one 16-bit word, from
+-32768 to 65535.
+
+Incorrect:
+
+    .data 100 70000
+
+Correct:
+
+    .data 100 7000"#,
+    ),
+    (
+        "E014",
+        "data does not fit in RAM",
+        r#"The values of a data directive run past the last addressable RAM
+word, 32767. Place the data lower or split it up.
+
+Incorrect:
+
+    .data 32767 1, 2
+
+Correct:
+
+    .data 32766 1, 2"#,
+    ),
+    (
+        "E015",
+        "malformed instruction set description",
+        r#"The --isa file is not valid TOML or JSON, is missing a table, or
+`extends` an unknown instruction set. The only base is `hack`, with it
+the file lists just the entries it adds to the Hack tables."#,
+    ),
+    (
+        "E016",
+        "inconsistent instruction set tables",
+        r#"The --isa file parsed, but its tables cannot encode programs
+unambiguously: a code is not binary or has the wrong width, two entries
+share a code, a table is empty, a symbol name is invalid, or a comp code
+would make a C-instruction look like an A-instruction. The message names
+the offending entry."#,
+    ),
+    (
+        "E017",
+        "bad export",
+        r#"An object exports a label it does not define, or two linked objects
+export the same label. Labels are local to their object unless exported,
+so helpers such as LOOP need no export and never clash; only the entry
+points other objects load need one.
+
+Incorrect:
+
+    .export MULT
+    (MULTIPLY)
+
+Correct:
+
+    .export MULT
+    (MULT)"#,
+    ),
+    (
+        "E018",
+        "malformed object or library",
+        r#"A file given to `hack link` or `hack lib` is not an object written by
+`hack -c` or a library written by `hack lib`. Libraries are recognized by
+their .hlib extension, everything else is read as an object. Rebuild the
+file from its source."#,
+    ),
+    (
+        "E019",
+        "malformed image",
+        r#"The image given to `hack bitmap` is not a plain (P1) or raw (P4)
+PBM, nor a PNG, or it is cut short: its raster holds fewer pixels than
+the width and height of its header. Greyscale PGM and colour PPM files
+are not read; convert them to PBM or PNG first."#,
+    ),
+    (
+        "E020",
+        "image off the screen",
+        r#"The image drawn at the given position reaches past the right or
+bottom edge of the 512x256 screen. Move it with --x and --y, or crop it.
+
+Incorrect, a 32x32 sprite:
+
+    hack bitmap ship.pbm --x 500
+
+Correct:
+
+    hack bitmap ship.pbm --x 480"#,
+    ),
+];
+
+impl Explain {
+    /// Summary and explanation of `code`, which is matched case-insensitively.
+    pub fn explain(code: &str) -> Option<String> {
+        let code = code.trim().to_uppercase();
+        CODES
+            .iter()
+            .find(|(id, _, _)| *id == code)
+            .map(|(id, summary, text)| format!("{}: {}\n\n{}\n", id, summary, text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain() {
+        let text = Explain::explain("e004").unwrap();
+        assert!(text.starts_with("E004: illegal A-instruction\n\n"));
+        assert!(text.contains("Incorrect:") && text.contains("Correct:"));
+        assert_eq!(Explain::explain("E999"), None);
+    }
+
+    #[test]
+    fn test_codes_are_documented() {
+        // every code reported anywhere in the sources has an entry
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/model");
+        let mut used = std::collections::BTreeSet::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let source = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+            for (i, _) in source.match_indices("\"E0") {
+                let code = &source[i + 1..i + 5];
+                if code[1..].bytes().all(|b| b.is_ascii_digit()) {
+                    used.insert(code.to_owned());
+                }
+            }
+        }
+        let listed: std::collections::BTreeSet<String> =
+            CODES.iter().map(|(id, _, _)| id.to_string()).collect();
+        assert_eq!(used, listed);
+    }
+}
@@ -0,0 +1,229 @@
+/**
+ * Assembly-time expressions
+ *
+ * EXPR: OR
+ * OR: AND ('||' AND)*
+ * AND: EQ ('&&' EQ)*
+ * EQ: REL (('==' | '!=') REL)*
+ * REL: BITOR (('<' | '<=' | '>' | '>=') BITOR)*
+ * BITOR: BITAND ('|' BITAND)*
+ * BITAND: SUM ('&' SUM)*
+ * SUM: PRODUCT (('+' | '-') PRODUCT)*
+ * PRODUCT: UNARY (('*' | '/' | '%') UNARY)*
+ * UNARY: ('-' | '!') UNARY | PRIMARY
+ * PRIMARY: NUMBER | SYMBOL | '(' EXPR ')'
+ *
+ * Comparisons and logical operators yield 1 or 0.
+ */
+pub struct Expr<'a> {
+    tokens: Vec<String>,
+    index: usize,
+    resolve: &'a dyn Fn(&str) -> Option<i64>,
+}
+
+impl<'a> Expr<'a> {
+    pub fn eval(s: &str, resolve: &'a dyn Fn(&str) -> Option<i64>) -> Result<i64, String> {
+        let mut expr = Expr {
+            tokens: Expr::tokenize(s)?,
+            index: 0,
+            resolve,
+        };
+        if expr.tokens.is_empty() {
+            return Err("Empty expression".into());
+        }
+        let value = expr.or()?;
+        match expr.peek() {
+            Some(t) => Err(format!("Unexpected {} in expression", t)),
+            None => Ok(value),
+        }
+    }
+
+    pub fn is_symbol_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || "_.$:".contains(c)
+    }
+
+    fn tokenize(s: &str) -> Result<Vec<String>, String> {
+        let chars: Vec<char> = s.chars().collect();
+        let mut ret = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c.is_whitespace() {
+                i += 1;
+            } else if Expr::is_symbol_char(c) {
+                let start = i;
+                while i < chars.len() && Expr::is_symbol_char(chars[i]) {
+                    i += 1;
+                }
+                ret.push(chars[start..i].iter().collect());
+            } else {
+                let pair: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+                if ["<=", ">=", "==", "!=", "&&", "||"].contains(&pair.as_str()) {
+                    ret.push(pair);
+                    i += 2;
+                } else if "+-*/%&|<>!()".contains(c) {
+                    ret.push(c.to_string());
+                    i += 1;
+                } else {
+                    return Err(format!("Unexpected character {} in expression", c));
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.index).map(|t| t.as_str())
+    }
+
+    fn accept(&mut self, ops: &[&str]) -> Option<String> {
+        let t = self.peek().filter(|t| ops.contains(t))?.to_owned();
+        self.index += 1;
+        Some(t)
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[&str],
+        next: fn(&mut Expr<'a>) -> Result<i64, String>,
+    ) -> Result<i64, String> {
+        let mut lhs = next(self)?;
+        while let Some(op) = self.accept(ops) {
+            let rhs = next(self)?;
+            lhs = match op.as_str() {
+                "||" => ((lhs != 0) || (rhs != 0)) as i64,
+                "&&" => ((lhs != 0) && (rhs != 0)) as i64,
+                "==" => (lhs == rhs) as i64,
+                "!=" => (lhs != rhs) as i64,
+                "<" => (lhs < rhs) as i64,
+                "<=" => (lhs <= rhs) as i64,
+                ">" => (lhs > rhs) as i64,
+                ">=" => (lhs >= rhs) as i64,
+                "|" => lhs | rhs,
+                "&" => lhs & rhs,
+                "+" => Expr::checked(lhs.checked_add(rhs))?,
+                "-" => Expr::checked(lhs.checked_sub(rhs))?,
+                "*" => Expr::checked(lhs.checked_mul(rhs))?,
+                _ if rhs == 0 => return Err("Division by zero in expression".into()),
+                "/" => Expr::checked(lhs.checked_div(rhs))?,
+                _ => Expr::checked(lhs.checked_rem(rhs))?,
+            };
+        }
+        Ok(lhs)
+    }
+
+    /// Arithmetic is on 64 bits, a result that does not fit is an error.
+    fn checked(value: Option<i64>) -> Result<i64, String> {
+        value.ok_or_else(|| "Overflow in expression".to_owned())
+    }
+
+    fn or(&mut self) -> Result<i64, String> {
+        self.binary(&["||"], Expr::and)
+    }
+
+    fn and(&mut self) -> Result<i64, String> {
+        self.binary(&["&&"], Expr::eq)
+    }
+
+    fn eq(&mut self) -> Result<i64, String> {
+        self.binary(&["==", "!="], Expr::rel)
+    }
+
+    fn rel(&mut self) -> Result<i64, String> {
+        self.binary(&["<", "<=", ">", ">="], Expr::bitor)
+    }
+
+    fn bitor(&mut self) -> Result<i64, String> {
+        self.binary(&["|"], Expr::bitand)
+    }
+
+    fn bitand(&mut self) -> Result<i64, String> {
+        self.binary(&["&"], Expr::sum)
+    }
+
+    fn sum(&mut self) -> Result<i64, String> {
+        self.binary(&["+", "-"], Expr::product)
+    }
+
+    fn product(&mut self) -> Result<i64, String> {
+        self.binary(&["*", "/", "%"], Expr::unary)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.accept(&["-", "!"]).as_deref() {
+            Some("-") => Expr::checked(self.unary()?.checked_neg()),
+            Some(_) => Ok((self.unary()? == 0) as i64),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, String> {
+        if self.accept(&["("]).is_some() {
+            let value = self.or()?;
+            if self.accept(&[")"]).is_none() {
+                return Err("Missing ) in expression".into());
+            }
+            return Ok(value);
+        }
+        let t = match self.peek() {
+            Some(t) => t.to_owned(),
+            None => return Err("Unexpected end of expression".into()),
+        };
+        self.index += 1;
+        if t.starts_with(|c: char| c.is_ascii_digit()) {
+            return t
+                .parse::<i64>()
+                .map_err(|_| format!("{} is not a number", t));
+        }
+        if !Expr::is_symbol_char(t.chars().next().unwrap()) {
+            return Err(format!("Unexpected {} in expression", t));
+        }
+        match (self.resolve)(&t) {
+            Some(v) => Ok(v),
+            None => Err(format!("{} is not defined", t)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(s: &str) -> Option<i64> {
+        match s {
+            "END" => Some(120),
+            "BUFFER" => Some(16320),
+            "SCREEN" => Some(16384),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(Expr::eval("1 + 2 * 3", &resolve), Ok(7));
+        assert_eq!(Expr::eval("(1 + 2) * 3", &resolve), Ok(9));
+        assert_eq!(Expr::eval("-4 + 10 % 4", &resolve), Ok(-2));
+        assert_eq!(Expr::eval("END < 32768", &resolve), Ok(1));
+        assert_eq!(Expr::eval("BUFFER+64 <= SCREEN", &resolve), Ok(1));
+        assert_eq!(Expr::eval("BUFFER+65 <= SCREEN", &resolve), Ok(0));
+        assert_eq!(Expr::eval("END > 0 && !(END == 5)", &resolve), Ok(1));
+        assert_eq!(Expr::eval("6 & 3 | 8", &resolve), Ok(10));
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        assert!(Expr::eval("", &resolve).is_err());
+        assert!(Expr::eval("FOO + 1", &resolve).is_err());
+        assert!(Expr::eval("(1 + 2", &resolve).is_err());
+        assert!(Expr::eval("1 2", &resolve).is_err());
+        assert!(Expr::eval("1 / 0", &resolve).is_err());
+        assert!(Expr::eval("1 # 2", &resolve).is_err());
+        let overflow = Err("Overflow in expression".to_owned());
+        assert_eq!(Expr::eval("9223372036854775807 + 1 > 0", &resolve), overflow);
+        assert_eq!(Expr::eval("-9223372036854775807 - 2", &resolve), overflow);
+        assert_eq!(Expr::eval("4294967296 * 4294967296", &resolve), overflow);
+        assert_eq!(Expr::eval("-(-9223372036854775807 - 1)", &resolve), overflow);
+        assert_eq!(Expr::eval("(-9223372036854775807 - 1) / -1", &resolve), overflow);
+        assert_eq!(Expr::eval("(-9223372036854775807 - 1) % -1", &resolve), overflow);
+    }
+}
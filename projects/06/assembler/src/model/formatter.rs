@@ -0,0 +1,169 @@
+use super::base::*;
+use super::dialect::Dialect;
+use super::error::*;
+use super::isa::Isa;
+use super::lexer::*;
+use super::strutil::Strutil;
+use crate::hack_report_line;
+
+pub const INDENT: &str = "    ";
+
+/**
+ * Formatter
+ *
+ * Labels are flush-left, instructions and directives indented, trailing
+ * comments aligned one column past the longest commented instruction and
+ * C-instructions spelled canonically. Full-line comments stay flush-left
+ * if they were, blank lines are kept.
+ */
+pub struct Formatter {
+    pub lexer: Lexer,
+    pub isa: Isa,
+}
+
+impl Formatter {
+    pub fn new(isa: Isa) -> Formatter {
+        Formatter {
+            lexer: Lexer::new(),
+            isa,
+        }
+    }
+
+    pub fn format(&mut self, source: &str) -> Result<String, Box<HackError>> {
+        // (code, comment) per line, code already indented
+        let mut lines: Vec<(String, Option<String>)> = Vec::new();
+        for (num, raw) in source.lines().enumerate() {
+            let (code, comment) = Strutil::split_comment(raw);
+            if code.is_empty() {
+                let indent = if comment.is_some() && raw.starts_with(char::is_whitespace) {
+                    INDENT
+                } else {
+                    ""
+                };
+                lines.push((
+                    String::new(),
+                    comment.map(|c| format!("{}{}", indent, c.trim_end())),
+                ));
+                continue;
+            }
+            if code.starts_with('.') {
+                lines.push((
+                    format!("{}{}", INDENT, code),
+                    comment.map(|c| c.trim_end().to_owned()),
+                ));
+            } else {
+                let code = self.format_code(num, raw)?;
+                let comment = self.lexer.comment.as_ref().map(|c| c.trim_end().to_owned());
+                lines.push((code, comment));
+            }
+        }
+        let column = lines
+            .iter()
+            .filter(|(code, comment)| !code.is_empty() && comment.is_some())
+            .map(|(code, _)| code.len() + 1)
+            .max()
+            .unwrap_or(0);
+        let mut ret = String::new();
+        for (code, comment) in lines {
+            match comment {
+                Some(c) if !code.is_empty() => {
+                    ret.push_str(&format!("{:width$}{}", code, c, width = column))
+                }
+                Some(c) => ret.push_str(&c),
+                None => ret.push_str(&code),
+            }
+            ret.push('\n');
+        }
+        Ok(ret)
+    }
+
+    fn format_code(&mut self, num: usize, raw: &str) -> Result<String, Box<HackError>> {
+        self.lexer.set(raw)?;
+        let tokens = &mut self.lexer.tokens;
+        let code = Strutil::split_comment(raw).0;
+        match self.lexer.cmd_type {
+            Some(CommandType::ACommand) => {
+                if tokens.len() != 2 || tokens[1].token_type == TOKENTYPE::EXPRESSION {
+                    hack_report_line!(num, code, "E004", "Illegal A command")
+                }
+                Ok(format!("{}@{}", INDENT, tokens[1].repr))
+            }
+            Some(CommandType::LCommand) => {
+                if tokens.len() != 3 || tokens[1].token_type != TOKENTYPE::SYMBOL {
+                    hack_report_line!(num, code, "E006", "No label found")
+                }
+                Ok(format!("({})", tokens[1].repr))
+            }
+            _ => {
+                Dialect::normalize(tokens, &self.isa);
+                let mut ret = String::from(INDENT);
+                for (i, t) in tokens.iter().enumerate() {
+                    let table = match t.token_type {
+                        TOKENTYPE::EQUAL | TOKENTYPE::SEMICOLON => None,
+                        _ if i + 1 < tokens.len()
+                            && tokens[i + 1].token_type == TOKENTYPE::EQUAL =>
+                        {
+                            Some(("DEST", &self.isa.dest))
+                        }
+                        _ if i > 0 && tokens[i - 1].token_type == TOKENTYPE::SEMICOLON => {
+                            Some(("JUMP", &self.isa.jump))
+                        }
+                        _ => Some(("COMP", &self.isa.comp)),
+                    };
+                    if let Some((name, table)) = table {
+                        if !table.contains_key(&t.repr) {
+                            hack_report_line!(
+                                num,
+                                code,
+                                "E003",
+                                format!("{} is not defined in table {}!", t.repr, name)
+                            )
+                        }
+                    }
+                    ret.push_str(&t.repr);
+                }
+                Ok(ret)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let source = "// header\n\n   @R0\n   D=M              // D = first number\n(LOOP)\n  // inner\nDM = A+D;JMP   //x   \n   @LOOP\n   0;JMP\n  .warning \"w\"\n";
+        let expected = "// header\n\n    @R0\n    D=M        // D = first number\n(LOOP)\n    // inner\n    MD=D+A;JMP //x\n    @LOOP\n    0;JMP\n    .warning \"w\"\n";
+        let mut formatter = Formatter::new(Isa::hack());
+        let out = formatter.format(source).unwrap();
+        assert_eq!(out, expected);
+        assert_eq!(formatter.format(&out).unwrap(), out);
+    }
+
+    #[test]
+    fn test_format_errors() {
+        let mut formatter = Formatter::new(Isa::hack());
+        assert!(formatter.format("D=Q\n").is_err());
+        assert!(formatter.format("@1+2\n").is_err());
+        assert!(formatter.format("( )\n").is_err());
+    }
+
+    #[test]
+    fn test_format_is_stable_on_projects() {
+        let mut formatter = Formatter::new(Isa::hack());
+        for name in ["../max/Max.asm", "../rect/Rect.asm", "../pong/Pong.asm"].iter() {
+            let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(name);
+            let source = std::fs::read_to_string(path).unwrap();
+            let once = formatter.format(&source).unwrap();
+            assert_eq!(formatter.format(&once).unwrap(), once);
+            let strip = |s: &str| -> Vec<String> {
+                s.lines()
+                    .map(|l| l.split_whitespace().collect::<String>())
+                    .collect()
+            };
+            assert_eq!(strip(&once), strip(&source));
+        }
+    }
+}
@@ -4,20 +4,37 @@ use super::error::*;
 use super::lexer::*;
 use super::parser::*;
 use super::strutil::Strutil;
+use crate::hack_report_line;
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// A line of the single pass: a label, an encoded word, or an A-instruction
+/// whose symbol is patched once every label is known.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Word {
+    Label(String),
+    Code(String),
+    Symbol(String),
+}
 
 pub struct HPU {
-    pub path: std::path::PathBuf,
-    pub command: Command,
     pub parser: Parser,
     pub lexer: Lexer,
     pub valid_line: usize,
 }
 
+impl Default for HPU {
+    fn default() -> HPU {
+        HPU::new()
+    }
+}
+
 impl HPU {
-    pub fn new(path: &std::path::PathBuf) -> HPU {
+    pub fn new() -> HPU {
         HPU {
-            path: path.clone(),
-            command: Command::new(),
             parser: Parser::new(),
             lexer: Lexer::new(),
             valid_line: 0,
@@ -25,48 +42,20 @@ impl HPU {
     }
 
     pub fn is_comment(s: &str) -> bool {
-        let mut iter = s.chars();
-        let _1: char;
-        match iter.next() {
-            Some(ch) => {
-                _1 = ch;
-            }
-            _ => {
-                return false;
-            }
-        }
-        let _2: char;
-        match iter.next() {
-            Some(ch) => {
-                _2 = ch;
-            }
-            _ => {
-                return false;
-            }
-        }
-        if _1 == '/' && _2 == '/' {
-            return true;
-        }
-        return false;
+        s.starts_with("//")
     }
 
     pub fn should_skip(s: &str) -> bool {
-        if Strutil::empty_line(s) {
-            return true;
-        } else if HPU::is_comment(s) {
-            return true;
-        } else {
-            return false;
-        }
+        Strutil::empty_line(s) || HPU::is_comment(s)
     }
 
-    pub fn command_type(s: &String) -> CommandType {
+    pub fn command_type(s: &str) -> CommandType {
         if s.starts_with("@") {
-            return CommandType::ACommand;
+            CommandType::ACommand
         } else if s.starts_with("(") && s.ends_with(")") {
-            return CommandType::LCommand;
+            CommandType::LCommand
         } else {
-            return CommandType::CCommand;
+            CommandType::CCommand
         }
     }
 
@@ -75,13 +64,13 @@ impl HPU {
         num: usize,
         line: &'a str,
     ) -> Result<String, Box<HackError>> {
-        if HPU::should_skip(&line) {
+        if HPU::should_skip(line) {
             return Ok("".into());
         }
         self.lexer.set(line)?;
         let mut parg = ParserArg {
             parser: Some(&mut self.parser),
-            tokens: Some(Box::new(self.lexer.tokens.clone())),
+            tokens: Some(self.lexer.tokens.clone()),
             index: Some(Box::new(0)),
             content: line.into(),
             line_num: Some(Box::new(num)),
@@ -92,11 +81,14 @@ impl HPU {
                 let result = parser.result.as_ref().unwrap();
                 match result.t.as_ref().unwrap() {
                     CommandType::ACommand => Coder::translate_a(
+                        &parser.isa,
                         parser.map.as_mut().unwrap(),
                         parser.varmem.as_mut().unwrap(),
                         result.ar.as_ref().unwrap(),
                     ),
-                    CommandType::CCommand => Coder::translate_c(result.cr.as_ref().unwrap()),
+                    CommandType::CCommand => {
+                        Coder::translate_c(&parser.isa, result.cr.as_ref().unwrap())
+                    }
                     CommandType::LCommand => Ok("".to_owned()),
                 }
             }
@@ -104,6 +96,96 @@ impl HPU {
         }
     }
 
+    /// Lexes and parses `line` once, symbols not yet defined are left to the caller.
+    pub fn single_pass(&mut self, num: usize, line: &str) -> Result<Option<Word>, Box<HackError>> {
+        let word = self.parse(num, line)?;
+        Ok(self.resolve(word))
+    }
+
+    /// Encodes a symbol already in the symbol table, e.g. a label defined above.
+    pub fn resolve(&self, word: Option<Word>) -> Option<Word> {
+        match word {
+            Some(Word::Symbol(symbol)) => match self.parser.map.as_ref().unwrap().get(&symbol) {
+                Some(address) => Some(Word::Code(format!("0{:015b}", address))),
+                None => Some(Word::Symbol(symbol)),
+            },
+            word => word,
+        }
+    }
+
+    /// `single_pass` without the labels seen so far: every symbol but the
+    /// predefined ones is left to the caller, so the result depends on the
+    /// text of `line` alone and can be cached.
+    pub fn parse(&mut self, num: usize, line: &str) -> Result<Option<Word>, Box<HackError>> {
+        if HPU::should_skip(line) {
+            return Ok(None);
+        }
+        self.lexer.set(line)?;
+        let mut parg = ParserArg {
+            parser: Some(&mut self.parser),
+            tokens: Some(self.lexer.tokens.clone()),
+            index: Some(Box::new(0)),
+            content: line.into(),
+            line_num: Some(Box::new(num)),
+        };
+        let arg = Parser::parse_command(&mut parg)?;
+        let parser = arg.parser.as_mut().unwrap();
+        let result = parser.result.as_ref().unwrap();
+        let word = match result.t.as_ref().unwrap() {
+            CommandType::ACommand => {
+                let value = &result.ar.as_ref().unwrap().value;
+                let defined =
+                    value.parse::<i32>().is_ok() || parser.isa.symbols.contains_key(value);
+                if !defined {
+                    return Ok(Some(Word::Symbol(value.clone())));
+                }
+                Word::Code(Coder::translate_a(
+                    &parser.isa,
+                    parser.map.as_mut().unwrap(),
+                    parser.varmem.as_mut().unwrap(),
+                    result.ar.as_ref().unwrap(),
+                )?)
+            }
+            CommandType::CCommand => {
+                Word::Code(Coder::translate_c(&parser.isa, result.cr.as_ref().unwrap())?)
+            }
+            CommandType::LCommand => Word::Label(result.lr.as_ref().unwrap().label.clone()),
+        };
+        Ok(Some(word))
+    }
+
+    /// Assembles a program held in memory with both passes, one word per
+    /// instruction. Directives and includes are expanded by `Assembler`.
+    pub fn assemble(&mut self, source: &str) -> Result<Vec<String>, Box<HackError>> {
+        let lines: Vec<(usize, String)> = source
+            .lines()
+            .enumerate()
+            .map(|(num, line)| (num, Strutil::split_comment(line).0.to_owned()))
+            .collect();
+        for (num, line) in &lines {
+            self.first_pass(&(*num, line.clone()))?;
+            if self.valid_line > ROM_SIZE {
+                hack_report_line!(
+                    *num,
+                    line,
+                    "E007",
+                    format!(
+                        "ROM overflow: the program needs more than {} words",
+                        ROM_SIZE
+                    )
+                )
+            }
+        }
+        let mut ret = Vec::new();
+        for (num, line) in &lines {
+            let word = self.second_pass(*num, line)?;
+            if !word.is_empty() {
+                ret.push(word);
+            }
+        }
+        Ok(ret)
+    }
+
     pub fn first_pass<'a>(
         &'a mut self,
         data: &'a (usize, String),
@@ -116,7 +198,7 @@ impl HPU {
             self.lexer.set(&data.1)?;
             let mut parg = ParserArg {
                 parser: Some(&mut self.parser),
-                tokens: Some(Box::new(self.lexer.tokens.clone())),
+                tokens: Some(self.lexer.tokens.clone()),
                 index: Some(Box::new(0)),
                 content: data.1.clone(),
                 line_num: Some(Box::new(data.0)),
@@ -153,11 +235,11 @@ mod tests {
     use super::*;
     #[test]
     fn test_comand_detection() {
-        assert_eq!(Strutil::empty_line("\n"), true);
-        assert_eq!(Strutil::empty_line(""), true);
-        assert_eq!(HPU::is_comment(""), false);
-        assert_eq!(HPU::is_comment("/"), false);
-        assert_eq!(HPU::is_comment("//"), true);
+        assert!(Strutil::empty_line("\n"));
+        assert!(Strutil::empty_line(""));
+        assert!(!HPU::is_comment(""));
+        assert!(!HPU::is_comment("/"));
+        assert!(HPU::is_comment("//"));
         let a = String::from("@INFINITE_LOOP");
         let c = String::from("M=-1");
         let l = String::from("(INFINITE_LOOP)");
@@ -165,6 +247,31 @@ mod tests {
         assert_eq!(HPU::command_type(&c), CommandType::CCommand);
         assert_eq!(HPU::command_type(&l), CommandType::LCommand);
     }
+    #[test]
+    fn test_assemble() {
+        let mut hpu = HPU::new();
+        hpu.parser.verbose = false;
+        let words = hpu
+            .assemble("(LOOP)\n  @i // counter\n  M=M+1\n\n  @LOOP\n  0;JMP\n")
+            .unwrap();
+        assert_eq!(
+            words,
+            vec![
+                "0000000000010000",
+                "1111110111001000",
+                "0000000000000000",
+                "1110101010000111"
+            ]
+        );
+        let e = HPU::new().assemble("@R0\nD=Q\n").unwrap_err();
+        assert_eq!((e.code, e.source_line_num), ("E003", Some(1)));
+        for constant in ["@32768", "@99999", "@99999999999999999999"].iter() {
+            let e = HPU::new().parse(0, constant).unwrap_err();
+            assert_eq!(e.code, "E004", "{}", constant);
+        }
+        assert!(HPU::new().parse(0, "@32767").is_ok());
+    }
+
     #[test]
     fn test_parse_command() -> Result<(), Box<HackError>> {
         let mut lexer = Lexer::new();
@@ -174,7 +281,7 @@ mod tests {
         let mut parser = Parser::new();
         let mut parg = ParserArg {
             parser: Some(&mut parser),
-            tokens: Some(Box::new(lexer.tokens.clone())),
+            tokens: Some(lexer.tokens.clone()),
             index: Some(Box::new(0)),
             line_num: Some(Box::new(0)),
             content: input.into(),
@@ -192,7 +299,7 @@ mod tests {
 
         let mut parg = ParserArg {
             parser: Some(&mut parser),
-            tokens: Some(Box::new(lexer.tokens.clone())),
+            tokens: Some(lexer.tokens.clone()),
             index: Some(Box::new(0)),
             line_num: Some(Box::new(0)),
             content: input.into(),
@@ -211,7 +318,7 @@ mod tests {
 
         let mut parg = ParserArg {
             parser: Some(&mut parser),
-            tokens: Some(Box::new(lexer.tokens.clone())),
+            tokens: Some(lexer.tokens.clone()),
             index: Some(Box::new(0)),
             line_num: Some(Box::new(0)),
             content: input.into(),
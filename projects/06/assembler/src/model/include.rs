@@ -0,0 +1,395 @@
+use super::error::*;
+use super::strutil::Strutil;
+use crate::hack_report_line;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/**
+ * Includes and the standard library
+ *
+ * .include <std/mult.asm>   a routine bundled with the assembler
+ * .include "lib/draw.asm"   a file, relative to the including file
+ *
+ * The included lines replace the directive and carry its line number;
+ * a file included twice is spliced in once. Included code runs wherever
+ * it lands, so `.include` goes after the program's halt loop, where
+ * nothing falls through into it. Standard routines are called with their
+ * arguments in R13, R14 and R15 and the return address in D:
+ *
+ * @R0      D=M     @R13    M=D     // R13 = first operand
+ * @R1      D=M     @R14    M=D     // R14 = second operand
+ * @RET     D=A     @std.mult       0;JMP
+ * (RET)                            // R13 = D = R0 * R1
+ * (END)    @END    0;JMP
+ * .include <std/mult.asm>
+ *
+ * routine  arguments                       results                  clobbers
+ * mult     R13, R14                        R13 = D = R13 * R14
+ * div      R13, R14                        R13 = D = R13 / R14,
+ *                                          R14 = R13 % R14
+ * fill     R13 address, R14 count, R15     nothing                  R13, R14
+ * clear                                    nothing
+ * rect     R13 word, R14 rows, R15 words   nothing                  R13, R14
+ * key                                      R13 = D = key code
+ *
+ * D holds the return address on entry, so no routine preserves it; the
+ * registers above are otherwise left as they were. Labels and variables
+ * of the library all start with `std.`, a prefix programs may use only to
+ * load a routine's entry, such as `@std.mult`. Every routine `.export`s
+ * its entry, so `hack link --std` links the same files as objects instead.
+ */
+pub struct Include {
+    seen: HashSet<String>,
+    pub files: Vec<PathBuf>, // files read, std routines excepted
+}
+
+pub const STD: [(&str, &str); 6] = [
+    ("std/mult.asm", include_str!("../../std/mult.asm")),
+    ("std/div.asm", include_str!("../../std/div.asm")),
+    ("std/fill.asm", include_str!("../../std/fill.asm")),
+    ("std/clear.asm", include_str!("../../std/clear.asm")),
+    ("std/rect.asm", include_str!("../../std/rect.asm")),
+    ("std/key.asm", include_str!("../../std/key.asm")),
+];
+
+pub const RESERVED: &str = "std.";
+
+impl Default for Include {
+    fn default() -> Include {
+        Include::new()
+    }
+}
+
+impl Include {
+    pub fn new() -> Include {
+        Include {
+            seen: HashSet::new(),
+            files: Vec::new(),
+        }
+    }
+
+    pub fn is_include(code: &str) -> bool {
+        code.split_whitespace().next() == Some(".include")
+    }
+
+    /// Names programs call the standard routines by, e.g. `std.mult`.
+    fn entries() -> impl Iterator<Item = String> {
+        STD.iter()
+            .map(|(name, _)| format!("{}{}", RESERVED, &name[4..name.len() - 4]))
+    }
+
+    /// A symbol of the standard library other than an entry.
+    fn is_internal(symbol: &str) -> bool {
+        symbol.starts_with(RESERVED) && Include::entries().all(|e| e != symbol)
+    }
+
+    /// Lines of `text`, read from `dir`, with every include spliced in.
+    pub fn lines(
+        &mut self,
+        text: &str,
+        dir: &Path,
+    ) -> Result<Vec<(usize, String)>, Box<HackError>> {
+        self.splice(text, dir, false)
+    }
+
+    fn splice(
+        &mut self,
+        text: &str,
+        dir: &Path,
+        std: bool,
+    ) -> Result<Vec<(usize, String)>, Box<HackError>> {
+        let mut ret = Vec::new();
+        for (num, raw) in text.lines().enumerate() {
+            let code = Strutil::split_comment(raw).0;
+            if !Include::is_include(code) {
+                if !std && code.starts_with('(') && code[1..].trim_start().starts_with(RESERVED) {
+                    hack_report_line!(
+                        num,
+                        code,
+                        "E005",
+                        format!(
+                            "Labels starting with {} are reserved for the standard library",
+                            RESERVED
+                        )
+                    )
+                }
+                if !std && code.starts_with('@') && Include::is_internal(code[1..].trim()) {
+                    hack_report_line!(
+                        num,
+                        code,
+                        "E004",
+                        format!(
+                            "Symbols starting with {} are reserved for the standard library, \
+                             programs may only load its entries: {}",
+                            RESERVED,
+                            Include::entries().collect::<Vec<_>>().join(", ")
+                        )
+                    )
+                }
+                ret.push((num, raw.to_owned()));
+                continue;
+            }
+            let target = code[8..].trim();
+            let (key, text, dir, std) = if target.starts_with('<') && target.ends_with('>') {
+                let name = &target[1..target.len() - 1];
+                match STD.iter().find(|(n, _)| *n == name) {
+                    Some((_, text)) => (name.to_owned(), text.to_string(), dir.to_path_buf(), true),
+                    None => hack_report_line!(
+                        num,
+                        code,
+                        "E012",
+                        format!(
+                            "{} is not part of the standard library, which has {}",
+                            name,
+                            STD.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", ")
+                        )
+                    ),
+                }
+            } else if target.len() > 1 && target.starts_with('"') && target.ends_with('"') {
+                let path = dir.join(&target[1..target.len() - 1]);
+                let key = path
+                    .canonicalize()
+                    .unwrap_or_else(|_| path.clone())
+                    .display()
+                    .to_string();
+                // a file that cannot be read is listed too, fixing it is a change
+                if !self.seen.contains(&key) {
+                    self.files.push(path.clone());
+                }
+                let text = match std::fs::read_to_string(&path) {
+                    Ok(text) => text,
+                    Err(e) => hack_report_line!(
+                        num,
+                        code,
+                        "E008",
+                        format!("Could not read {}: {}", path.display(), e)
+                    ),
+                };
+                let dir = path.parent().unwrap_or(dir).to_path_buf();
+                (key, text, dir, false)
+            } else {
+                hack_report_line!(
+                    num,
+                    code,
+                    "E012",
+                    ".include expects <std/NAME.asm> or a quoted path"
+                )
+            };
+            if !self.seen.insert(key) {
+                continue;
+            }
+            for (_, line) in self.splice(&text, &dir, std)? {
+                ret.push((num, line));
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::assembler::create_assembler;
+    use crate::model::dialect::Dialect;
+    use crate::model::isa::Isa;
+
+    #[test]
+    fn test_lines() {
+        let dir = std::env::temp_dir().join(format!("hack-{}-include", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/a.asm"), "(A)\n.include \"b.asm\"\n").unwrap();
+        std::fs::write(dir.join("lib/b.asm"), "(B)\n.include <std/key.asm>\n").unwrap();
+        let text = "@A\n.include \"lib/a.asm\" // twice\n.include <std/key.asm>\n.include \"lib/b.asm\"\n0;JMP\n";
+        let mut include = Include::new();
+        let lines = include.lines(text, &dir).unwrap();
+        let key = STD[5].1.lines().count();
+        assert_eq!(lines.len(), 1 + 1 + 1 + key + 1);
+        assert_eq!(lines[0], (0, "@A".to_owned()));
+        assert_eq!(lines[1], (1, "(A)".to_owned()));
+        assert_eq!(lines[2], (1, "(B)".to_owned()));
+        assert!(lines[3..3 + key].iter().all(|(num, _)| *num == 1));
+        assert_eq!(lines[3 + key], (4, "0;JMP".to_owned()));
+        assert_eq!(
+            include.files,
+            vec![dir.join("lib/a.asm"), dir.join("lib/b.asm")]
+        );
+
+        let mut include = Include::new();
+        let e = include.lines("(std.loop)\n", &dir).unwrap_err();
+        assert_eq!(e.code, "E005");
+        assert_eq!(
+            include
+                .lines(".include <std/sqrt.asm>\n", &dir)
+                .unwrap_err()
+                .code,
+            "E012"
+        );
+        assert_eq!(
+            include
+                .lines(".include lib/a.asm\n", &dir)
+                .unwrap_err()
+                .code,
+            "E012"
+        );
+        assert_eq!(
+            include
+                .lines(".include \"nope.asm\"\n", &dir)
+                .unwrap_err()
+                .code,
+            "E008"
+        );
+        let e = include.lines("@std.mult.ret\n", &dir).unwrap_err();
+        assert_eq!(e.code, "E004");
+        assert!(e.comment.contains("std.mult, std.div"));
+        assert!(include.lines("@std.mult\n@ std.key\n", &dir).is_ok());
+    }
+
+    const SCREEN: usize = 16384;
+    const KBD: usize = 24576;
+
+    /// Calls `routine` with `args` in R13, R14 and R15 on a Hack CPU, returns
+    /// D once the program reaches its halt loop.
+    fn call(routine: &str, args: &[i16], ram: &mut [i16]) -> i16 {
+        let text = format!(
+            "@RET\nD=A\n@std.{0}\n0;JMP\n(RET)\n(END)\n@END\n0;JMP\n.include <std/{0}.asm>\n",
+            routine
+        );
+        let path = std::env::temp_dir()
+            .join(format!("hack-{}-std", std::process::id()))
+            .join("Call.asm");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut assembler = create_assembler(&path, Isa::hack(), Dialect::Strict);
+        assembler.set_verbose(false);
+        assembler.set_source(&text);
+        assembler.run().unwrap();
+        let rom: Vec<u16> = assembler
+            .rom()
+            .iter()
+            .map(|(_, w)| u16::from_str_radix(w, 2).unwrap())
+            .collect();
+        for (i, arg) in args.iter().enumerate() {
+            ram[13 + i] = *arg;
+        }
+        let (mut a, mut d, mut pc) = (0i16, 0i16, 0);
+        for _ in 0..1_000_000 {
+            let word = rom[pc];
+            if word == pc as u16 && rom[pc + 1] == 0b1110_1010_1000_0111 {
+                return d;
+            }
+            if word & 0x8000 == 0 {
+                a = word as i16;
+                pc += 1;
+                continue;
+            }
+            let bit = |n: u16| (word >> n) & 1 == 1;
+            let address = a as u16 as usize;
+            let (mut x, mut y) = (d, if bit(12) { ram[address] } else { a });
+            if bit(11) {
+                x = 0;
+            }
+            if bit(10) {
+                x = !x;
+            }
+            if bit(9) {
+                y = 0;
+            }
+            if bit(8) {
+                y = !y;
+            }
+            let mut out = if bit(7) { x.wrapping_add(y) } else { x & y };
+            if bit(6) {
+                out = !out;
+            }
+            if bit(3) {
+                ram[address] = out;
+            }
+            if bit(5) {
+                a = out;
+            }
+            if bit(4) {
+                d = out;
+            }
+            let jump = (bit(2) && out < 0) || (bit(1) && out == 0) || (bit(0) && out > 0);
+            pc = if jump { address } else { pc + 1 };
+        }
+        panic!("std.{} did not return", routine)
+    }
+
+    #[test]
+    fn test_mult() {
+        for (x, y) in [(6, 7), (-3, 7), (7, -3), (-4, -5), (0, -1), (300, 300)].iter() {
+            let mut ram = vec![0; 32768];
+            let d = call("mult", &[*x, *y, 99], &mut ram);
+            let product = x.wrapping_mul(*y);
+            assert_eq!((ram[13], d, ram[14], ram[15]), (product, product, *y, 99));
+        }
+    }
+
+    #[test]
+    fn test_div() {
+        for (x, y, q, r) in [
+            (17, 5, 3, 2),
+            (4, 5, 0, 4),
+            (32767, 1, 32767, 0),
+            (-1, 2, 32767, 1), // read as 65535
+            (-2, 32767, 2, 0),
+            (9, 0, -1, 9),
+        ]
+        .iter()
+        {
+            let mut ram = vec![0; 32768];
+            let d = call("div", &[*x, *y, 99], &mut ram);
+            assert_eq!(
+                (ram[13], d, ram[14], ram[15]),
+                (*q, *q, *r, 99),
+                "{} / {}",
+                x,
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn test_fill() {
+        for count in [3, 0, -1].iter() {
+            let mut ram = vec![-5; 32768];
+            call("fill", &[100, *count, 7], &mut ram);
+            let n = (*count).max(0) as usize;
+            assert!(ram[100..100 + n].iter().all(|w| *w == 7));
+            assert_eq!((ram[99], ram[100 + n], ram[15]), (-5, -5, 7));
+        }
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut ram = vec![-1; 32768];
+        call("clear", &[], &mut ram);
+        assert!(ram[SCREEN..KBD].iter().all(|w| *w == 0));
+        assert_eq!((ram[SCREEN - 1], ram[KBD]), (-1, -1));
+        assert_eq!(&ram[13..16], &[-1, -1, -1]);
+    }
+
+    #[test]
+    fn test_rect() {
+        let corner = SCREEN + 2 * 32 + 3;
+        let mut ram = vec![0; 32768];
+        call("rect", &[corner as i16, 2, 2], &mut ram);
+        let black: Vec<usize> = (SCREEN..KBD).filter(|a| ram[*a] != 0).collect();
+        assert_eq!(black, vec![corner, corner + 1, corner + 32, corner + 33]);
+        assert!(black.iter().all(|a| ram[*a] == -1));
+        assert_eq!(ram[15], 2);
+        for (rows, words) in [(0, 2), (2, 0), (-1, 2)].iter() {
+            let mut ram = vec![0; 32768];
+            call("rect", &[corner as i16, *rows, *words], &mut ram);
+            assert!(ram[SCREEN..KBD].iter().all(|w| *w == 0));
+        }
+    }
+
+    #[test]
+    fn test_key() {
+        let mut ram = vec![0; 32768];
+        ram[KBD] = 65;
+        assert_eq!(call("key", &[0, 1, 2], &mut ram), 65);
+        assert_eq!(&ram[13..16], &[65, 1, 2]);
+    }
+}
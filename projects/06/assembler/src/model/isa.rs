@@ -0,0 +1,238 @@
+use super::base::*;
+use super::error::*;
+use crate::hack_report_less;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use serde::{Deserialize, Serialize};
+
+/**
+ * Instruction set description
+ *
+ * A C-instruction is encoded as PREFIX COMP DEST JUMP, the four fields
+ * together being 16 bits wide. The built-in Hack ISA is generated from
+ * the tables in base.rs; extended CPUs describe theirs in a TOML or JSON
+ * file, read with the std feature:
+ *
+ * extends = "hack"     # optional, start from the built-in tables
+ * prefix = "111"
+ * [comp]
+ * "D<<1" = "0100000"
+ * [dest] [jump] [symbols]
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Isa {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, skip_serializing)]
+    pub extends: Option<String>,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub comp: BTreeMap<String, String>,
+    #[serde(default)]
+    pub dest: BTreeMap<String, String>,
+    #[serde(default)]
+    pub jump: BTreeMap<String, String>,
+    #[serde(default)]
+    pub symbols: BTreeMap<String, i32>,
+}
+
+impl Default for Isa {
+    fn default() -> Isa {
+        Isa::hack()
+    }
+}
+
+impl Isa {
+    pub fn hack() -> Isa {
+        let table = |t: &[(&str, &str)]| {
+            t.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<BTreeMap<String, String>>()
+        };
+        Isa {
+            name: "hack".into(),
+            extends: None,
+            prefix: "111".into(),
+            comp: table(&COMP),
+            dest: table(&DEST),
+            jump: table(&JUMP),
+            symbols: PREDEFINE_SYMBOLS
+                .iter()
+                .map(|(k, v)| (k.to_string(), *v))
+                .collect(),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &std::path::Path) -> Result<Isa, Box<HackError>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => hack_report_less!(
+                "E008",
+                format!("Could not read {}: {}", path.display(), e)
+            ),
+        };
+        let json = path.extension().is_some_and(|e| e == "json");
+        Isa::parse(&text, json)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn parse(text: &str, json: bool) -> Result<Isa, Box<HackError>> {
+        let parsed: Result<Isa, String> = if json {
+            serde_json::from_str(text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(text).map_err(|e| e.to_string())
+        };
+        let mut isa = match parsed {
+            Ok(isa) => isa,
+            Err(e) => hack_report_less!("E015", format!("Malformed ISA description: {}", e)),
+        };
+        match isa.extends.take().as_deref() {
+            None => {}
+            Some("hack") => {
+                let mut base = Isa::hack();
+                if !isa.name.is_empty() {
+                    base.name = isa.name;
+                }
+                if !isa.prefix.is_empty() {
+                    base.prefix = isa.prefix;
+                }
+                base.comp.extend(isa.comp);
+                base.dest.extend(isa.dest);
+                base.jump.extend(isa.jump);
+                base.symbols.extend(isa.symbols);
+                isa = base;
+            }
+            Some(other) => hack_report_less!("E015", format!("Unknown base ISA {}", other)),
+        }
+        isa.validate()?;
+        Ok(isa)
+    }
+
+    pub fn validate(&self) -> Result<(), Box<HackError>> {
+        let binary = |s: &str| s.chars().all(|c| c == '0' || c == '1');
+        if !binary(&self.prefix) {
+            hack_report_less!("E016", format!("Prefix {} is not a binary string", self.prefix))
+        }
+        let comp = Isa::check_table("comp", &self.comp, true)?;
+        let dest = Isa::check_table("dest", &self.dest, false)?;
+        let jump = Isa::check_table("jump", &self.jump, false)?;
+        if self.prefix.len() + comp + dest + jump != 16 {
+            hack_report_less!("E016", format!(
+                "prefix, comp, dest and jump are {} bits wide, expected 16",
+                self.prefix.len() + comp + dest + jump
+            ))
+        }
+        for (k, v) in &self.comp {
+            if !format!("{}{}", self.prefix, v).starts_with('1') {
+                hack_report_less!("E016", format!("comp {} would encode as an A-instruction", k))
+            }
+        }
+        for (k, v) in &self.symbols {
+            if k.is_empty() || k.starts_with(|c: char| c.is_ascii_digit()) {
+                hack_report_less!("E016", format!("{} is not a valid symbol name", k))
+            }
+            if !(0..=32767).contains(v) {
+                hack_report_less!("E016", format!(
+                    "Symbol {} = {} does not fit in an A-instruction",
+                    k, v
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a table and returns the width of its encodings.
+    fn check_table(
+        name: &str,
+        table: &BTreeMap<String, String>,
+        zero_allowed: bool,
+    ) -> Result<usize, Box<HackError>> {
+        let mut width: Option<usize> = None;
+        let mut seen: BTreeMap<&str, &str> = BTreeMap::new();
+        for (k, v) in table {
+            if v.is_empty() || !v.chars().all(|c| c == '0' || c == '1') {
+                hack_report_less!("E016", format!("{} {} = {} is not a binary string", name, k, v))
+            }
+            if *width.get_or_insert(v.len()) != v.len() {
+                hack_report_less!("E016", format!("{} {} = {} has the wrong width", name, k, v))
+            }
+            if !zero_allowed && !v.contains('1') {
+                hack_report_less!("E016", format!(
+                    "{} {} = {} is reserved for no {}",
+                    name, k, v, name
+                ))
+            }
+            if let Some(other) = seen.insert(v, k) {
+                hack_report_less!("E016", format!(
+                    "{} {} and {} share the encoding {}",
+                    name, other, k, v
+                ))
+            }
+        }
+        match width {
+            Some(w) => Ok(w),
+            None => hack_report_less!("E016", format!("Table {} is empty", name)),
+        }
+    }
+
+    /// The all-zero field used when dest or jump is absent.
+    pub fn none(table: &BTreeMap<String, String>) -> String {
+        "0".repeat(table.values().next().map_or(3, |v| v.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin() {
+        let isa = Isa::hack();
+        isa.validate().unwrap();
+        assert_eq!(isa.comp["D+A"], "0000010");
+        assert_eq!(isa.symbols["KBD"], 24576);
+        assert_eq!(Isa::none(&isa.jump), "000");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_description_file() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("isa/hack.toml");
+        assert_eq!(Isa::load(&path).unwrap(), Isa::hack());
+        let json = serde_json::to_string(&Isa::hack()).unwrap();
+        assert_eq!(Isa::parse(&json, true).unwrap(), Isa::hack());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_extends() {
+        let isa = Isa::parse(
+            "extends = \"hack\"\nname = \"shift\"\n[comp]\n\"D<<1\" = \"0100000\"\n[symbols]\nIO = 24577\n",
+            false,
+        )
+        .unwrap();
+        assert_eq!(isa.name, "shift");
+        assert_eq!(isa.comp["D<<1"], "0100000");
+        assert_eq!(isa.comp["D+A"], "0000010");
+        assert_eq!(isa.symbols["IO"], 24577);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_validation() {
+        let shared = "extends = \"hack\"\n[comp]\n\"A+D\" = \"0000010\"\n";
+        assert!(Isa::parse(shared, false).is_err());
+        let wide = "extends = \"hack\"\n[jump]\nJXX = \"1000\"\n";
+        assert!(Isa::parse(wide, false).is_err());
+        let zero = "extends = \"hack\"\n[dest]\nX = \"000\"\n";
+        assert!(Isa::parse(zero, false).is_err());
+        let prefix = "extends = \"hack\"\nprefix = \"011\"\n";
+        assert!(Isa::parse(prefix, false).is_err());
+        assert!(Isa::parse("extends = \"arm\"\n", false).is_err());
+        assert!(Isa::parse("prefix = ", false).is_err());
+    }
+}
@@ -2,13 +2,22 @@ use super::base::*;
 use super::error::*;
 use super::strutil::Strutil;
 use crate::hack_report_less;
-use lazy_static::lazy_static;
-use regex::Regex;
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 
 #[derive(Debug)]
 pub struct Lexer {
     pub tokens: Vec<Token>,
     pub cmd_type: Option<CommandType>,
+    pub comment: Option<String>, // trailing comment, kept as trivia
+}
+
+impl Default for Lexer {
+    fn default() -> Lexer {
+        Lexer::new()
+    }
 }
 
 impl Lexer {
@@ -16,22 +25,24 @@ impl Lexer {
         Lexer {
             tokens: Vec::new(),
             cmd_type: None,
+            comment: None,
         }
     }
-    pub fn set<'a>(&mut self, expr: &'a str) -> Result<(), Box<HackError>> {
+    pub fn set(&mut self, expr: &str) -> Result<(), Box<HackError>> {
         self.tokens.clear();
         self.cmd_type = None;
+        let (expr, comment) = Strutil::split_comment(expr);
+        self.comment = comment.map(|c| c.to_owned());
         if Lexer::is_empty_line(expr) {
-            hack_report_less!("Empty line");
+            hack_report_less!("E001", "Empty line");
         }
-        let expr = expr.trim();
-        if expr.starts_with("@") {
+        if let Some(value) = expr.strip_prefix('@') {
             self.cmd_type = Some(CommandType::ACommand);
             self.tokens.push(Token {
                 repr: "@".into(),
                 token_type: TOKENTYPE::AT,
             });
-            Lexer::add_tokens(&mut self.tokens, &expr[1..])
+            Lexer::add_tokens(&mut self.tokens, value)
         } else if expr.starts_with("(") && expr.ends_with(")") {
             self.cmd_type = Some(CommandType::LCommand);
             self.tokens.push(Token {
@@ -46,17 +57,10 @@ impl Lexer {
             e
         } else {
             self.cmd_type = Some(CommandType::CCommand);
-            let mut expr = expr.clone();
+            let mut expr = expr;
             if Strutil::fall_within(expr, "=") {
                 let a: Vec<&str> = expr.split('=').collect();
-                let dest = Some(a[0]);
-                let e = Lexer::add_tokens(&mut self.tokens, dest.unwrap());
-                match e {
-                    Err(e_) => {
-                        return Err(e_);
-                    }
-                    Ok(_) => {}
-                }
+                Lexer::add_tokens(&mut self.tokens, a[0])?;
                 expr = a[1];
                 self.tokens.push(Token {
                     repr: "=".into(),
@@ -65,14 +69,7 @@ impl Lexer {
             }
             if Strutil::fall_within(expr, ";") {
                 let a: Vec<&str> = expr.split(';').collect();
-                let comp = Some(a[0]);
-                let e = Lexer::add_tokens(&mut self.tokens, comp.unwrap());
-                match e {
-                    Err(e_) => {
-                        return Err(e_);
-                    }
-                    Ok(_) => {}
-                }
+                Lexer::add_tokens(&mut self.tokens, a[0])?;
                 expr = a[1];
                 self.tokens.push(Token {
                     repr: ";".into(),
@@ -80,11 +77,11 @@ impl Lexer {
                 });
             }
             // JUMP
-            Lexer::add_tokens(&mut self.tokens, &expr)
+            Lexer::add_tokens(&mut self.tokens, expr)
         }
     }
 
-    pub fn add_tokens<'a>(v: &mut Vec<Token>, s: &'a str) -> Result<(), Box<HackError>> {
+    pub fn add_tokens(v: &mut Vec<Token>, s: &str) -> Result<(), Box<HackError>> {
         let subs = s.split_whitespace();
         for sub in subs {
             match Lexer::classify(sub) {
@@ -99,29 +96,42 @@ impl Lexer {
 
     pub fn is_empty_line(s: &str) -> bool {
         let iter = s.split_whitespace();
-        return iter.count() == 0;
+        iter.count() == 0
     }
 
     pub fn classify(s: &str) -> Result<Token, Box<HackError>> {
-        lazy_static! {
-            static ref NUMBER: Regex = Regex::new(r"^\d+$").unwrap();
-            static ref SYMBOL: Regex = Regex::new(r"^[_[:alpha:]]+[_0-9A-Za-z]*$").unwrap();
-        }
-        if NUMBER.is_match(s) {
-            return Ok(Token {
+        if Lexer::is_number(s) {
+            Ok(Token {
                 repr: s.into(),
                 token_type: TOKENTYPE::NUMBER,
-            });
-        } else if SYMBOL.is_match(s) {
-            return Ok(Token {
+            })
+        } else if Lexer::is_symbol(s) {
+            Ok(Token {
                 repr: s.into(),
                 token_type: TOKENTYPE::SYMBOL,
-            });
+            })
         } else {
-            return Ok(Token {
+            Ok(Token {
                 repr: s.into(),
                 token_type: TOKENTYPE::EXPRESSION,
-            });
+            })
+        }
+    }
+
+    /// `[0-9]+`
+    pub fn is_number(s: &str) -> bool {
+        !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+    }
+
+    /// `[_.$:A-Za-z][_.$:0-9A-Za-z]*`
+    pub fn is_symbol(s: &str) -> bool {
+        let extra = |b: u8| b"_.$:".contains(&b);
+        let mut bytes = s.bytes();
+        match bytes.next() {
+            Some(b) if b.is_ascii_alphabetic() || extra(b) => {
+                bytes.all(|b| b.is_ascii_alphanumeric() || extra(b))
+            }
+            _ => false,
         }
     }
 }
@@ -132,7 +142,7 @@ mod tests {
     #[test]
     fn test_lexer() {
         let mut lexer = Lexer::new();
-        lexer.set("@R2");
+        lexer.set("@R2").unwrap();
         println!("{:?}", lexer.tokens);
         assert_eq!(
             lexer.tokens[0],
@@ -148,7 +158,7 @@ mod tests {
                 token_type: TOKENTYPE::SYMBOL
             }
         );
-        lexer.set("@234");
+        lexer.set("@234").unwrap();
         println!("{:?}", lexer.tokens);
         assert_eq!(
             lexer.tokens[0],
@@ -164,5 +174,29 @@ mod tests {
                 token_type: TOKENTYPE::NUMBER
             }
         );
+        lexer.set("(ball.new$if_end:1)").unwrap();
+        assert_eq!(lexer.tokens[1].token_type, TOKENTYPE::SYMBOL);
+        assert_eq!(lexer.comment, None);
+        lexer.set("D=M   // D = first number").unwrap();
+        assert_eq!(lexer.tokens.len(), 3);
+        assert_eq!(lexer.comment.as_deref(), Some("// D = first number"));
+    }
+
+    #[test]
+    fn test_classify() {
+        assert!(Lexer::is_number("0"));
+        assert!(Lexer::is_number("32767"));
+        assert!(!Lexer::is_number("-1"));
+        assert!(!Lexer::is_number(""));
+        assert!(Lexer::is_symbol("std.mult.ret"));
+        assert!(Lexer::is_symbol("$x:1"));
+        assert!(Lexer::is_symbol("R15"));
+        assert!(!Lexer::is_symbol("1R"));
+        assert!(!Lexer::is_symbol("D+1"));
+        assert!(!Lexer::is_symbol("Ä"));
+        assert_eq!(
+            Lexer::classify("D|M").unwrap().token_type,
+            TOKENTYPE::EXPRESSION
+        );
     }
 }
@@ -0,0 +1,178 @@
+use super::base::*;
+use super::error::*;
+use super::object::*;
+use crate::hack_report_less;
+use std::collections::{HashMap, HashSet};
+
+/**
+ * Linker
+ *
+ * Every object given is linked, in order, the first one starting at ROM
+ * address 0. Library members are then pulled in, in name order and until
+ * nothing changes, while they export a symbol some linked object loads
+ * and no linked object exports. External symbols nobody exports become
+ * variables, allocated from `VARMEM_BASE` in order of first use across
+ * the whole program, so a name means the same RAM word in every object.
+ */
+pub struct Linker {}
+
+impl Linker {
+    /// The ROM image, one 16-bit word per entry.
+    pub fn link(
+        objects: &[(String, Object)],
+        libraries: &[Library],
+    ) -> Result<Vec<String>, Box<HackError>> {
+        let mut linked: Vec<(&str, &Object)> =
+            objects.iter().map(|(n, o)| (n.as_str(), o)).collect();
+        let mut pulled: HashSet<(usize, &str)> = HashSet::new();
+        loop {
+            let exported: HashSet<&str> = linked
+                .iter()
+                .flat_map(|(_, o)| o.exports.keys().map(String::as_str))
+                .collect();
+            let needed: HashSet<&str> = linked
+                .iter()
+                .flat_map(|(_, o)| o.externals())
+                .filter(|s| !exported.contains(s))
+                .collect();
+            let member = libraries.iter().enumerate().find_map(|(i, library)| {
+                library
+                    .members
+                    .iter()
+                    .find(|(name, member)| {
+                        !pulled.contains(&(i, name.as_str()))
+                            && member.exports.keys().any(|e| needed.contains(e.as_str()))
+                    })
+                    .map(|(name, member)| (i, name.as_str(), member))
+            });
+            match member {
+                Some((i, name, member)) => {
+                    pulled.insert((i, name));
+                    linked.push((name, member));
+                }
+                None => break,
+            }
+        }
+
+        let mut bases = Vec::new();
+        let mut globals: HashMap<&str, (usize, &str)> = HashMap::new(); // symbol -> (address, object)
+        let mut size = 0;
+        for (name, object) in &linked {
+            for (label, offset) in &object.exports {
+                if let Some((_, other)) = globals.insert(label, (size + offset, name)) {
+                    hack_report_less!(
+                        "E017",
+                        format!("{} is exported by both {} and {}", label, other, name)
+                    )
+                }
+            }
+            bases.push(size);
+            size += object.code.len();
+        }
+        if size > ROM_SIZE {
+            hack_report_less!(
+                "E007",
+                format!(
+                    "ROM overflow: the program needs {} of {} words",
+                    size, ROM_SIZE
+                )
+            )
+        }
+
+        let mut variables: HashMap<&str, usize> = HashMap::new();
+        let mut ret = Vec::with_capacity(size);
+        for ((_, object), base) in linked.iter().zip(bases) {
+            for slot in &object.code {
+                let address = match slot {
+                    Slot::Word(word) => {
+                        ret.push(word.clone());
+                        continue;
+                    }
+                    Slot::Relocate(offset) => base + offset,
+                    Slot::External(symbol) => match globals.get(symbol.as_str()) {
+                        Some((address, _)) => *address,
+                        None => {
+                            let next = VARMEM_BASE + variables.len();
+                            *variables.entry(symbol.as_str()).or_insert(next)
+                        }
+                    },
+                };
+                ret.push(format!("0{:015b}", address));
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JMP: &str = "1110101010000111";
+
+    fn object(code: Vec<Slot>, exports: &[(&str, usize)]) -> Object {
+        Object {
+            code,
+            exports: exports.iter().map(|(n, o)| (n.to_string(), *o)).collect(),
+        }
+    }
+
+    fn word(n: usize) -> String {
+        format!("0{:015b}", n)
+    }
+
+    #[test]
+    fn test_link() {
+        let jmp = Slot::Word(JMP.into());
+        let main = object(
+            vec![
+                Slot::External("x".into()),
+                Slot::External("SQUARE".into()),
+                jmp.clone(),
+                Slot::Relocate(0),
+            ],
+            &[],
+        );
+        let square = object(
+            vec![
+                Slot::External("y".into()),
+                Slot::External("x".into()),
+                jmp.clone(),
+            ],
+            &[("SQUARE", 0)],
+        );
+        let cube = object(vec![jmp.clone()], &[("CUBE", 0)]);
+        let library = Library {
+            members: vec![("cube".to_owned(), cube), ("square".to_owned(), square)]
+                .into_iter()
+                .collect(),
+        };
+        // cube is not needed, x is one variable in both objects
+        let rom = Linker::link(&[("main".into(), main.clone())], &[library]).unwrap();
+        assert_eq!(
+            rom,
+            vec![
+                word(16),
+                word(4),
+                JMP.into(),
+                word(0),
+                word(17),
+                word(16),
+                JMP.into()
+            ]
+        );
+
+        let twice = object(vec![jmp], &[("SQUARE", 0)]);
+        let e = Linker::link(
+            &[
+                ("main".into(), main),
+                ("a".into(), twice.clone()),
+                ("b".into(), twice),
+            ],
+            &[],
+        )
+        .unwrap_err();
+        assert_eq!(e.code, "E017");
+        assert_eq!(e.comment, "SQUARE is exported by both a and b");
+    }
+}
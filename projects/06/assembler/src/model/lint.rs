@@ -0,0 +1,324 @@
+use super::base::*;
+use super::cfg::Cfg;
+use super::dataflow::Dataflow;
+use super::directive::*;
+use super::error::*;
+use super::hpu::HPU;
+use super::include::Include;
+use super::isa::Isa;
+use super::strutil::Strutil;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+/**
+ * Linter
+ *
+ * L001 a C-instruction writes both A and M, M is addressed by the old A
+ * L002 code after an unconditional jump that no label or numeric jump target makes reachable
+ * L003 a jump whose target was loaded from a variable rather than a label
+ * L004 a write to the keyboard register KBD
+ * L005 a variable read before it is assigned on every path
+ * L006 D or A read before it is assigned on every path
+ *
+ * A rule is silenced for one instruction by a `// lint: allow(L001)`
+ * comment on the same line or on a comment line right above it.
+ */
+pub const RULES: [(&str, &str); 6] = [
+    (
+        "L001",
+        "writes A and M in the same instruction, M is addressed by the old A",
+    ),
+    ("L002", "unreachable code after an unconditional jump"),
+    ("L003", "jump target is a variable, not a label"),
+    ("L004", "write to the keyboard register KBD"),
+    ("L005", "reads a variable not assigned on every path:"),
+    ("L006", "reads a register not assigned on every path:"),
+];
+
+#[derive(Debug, PartialEq)]
+pub struct Lint {
+    pub id: &'static str,
+    pub line_num: usize,
+    pub line: String,
+    pub name: Option<String>, // variable or register the rule is about
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let message = RULES.iter().find(|(id, _)| *id == self.id).unwrap().1;
+        write!(
+            f,
+            "[{}]: {}: {} {}",
+            self.line_num, self.line, self.id, message
+        )?;
+        match &self.name {
+            Some(name) => write!(f, " {}", name),
+            None => Ok(()),
+        }
+    }
+}
+
+struct Instruction {
+    num: usize,
+    code: String,
+    t: CommandType,
+    allow: Vec<String>,
+    expanded: bool, // a line of a directive expansion other than its first
+}
+
+pub struct Linter {}
+
+impl Linter {
+    /// `uninit` names predefined registers whose reads count as uninitialized, e.g. R2.
+    /// Includes are read from `dir` and linted as part of the program.
+    pub fn lint(
+        source: &str,
+        dir: &Path,
+        isa: &Isa,
+        uninit: &[String],
+    ) -> Result<Vec<Lint>, Box<HackError>> {
+        let instructions = Linter::instructions(source, dir, isa)?;
+        let labels: HashSet<&str> = instructions
+            .iter()
+            .filter(|i| i.t == CommandType::LCommand)
+            .map(|i| &i.code[1..i.code.len() - 1])
+            .collect();
+        let targets = Linter::numeric_targets(&instructions);
+        let mut ret = Vec::new();
+        let mut report = |i: &Instruction, id: &'static str| {
+            if !i.allow.iter().any(|a| a == id) {
+                ret.push(Lint {
+                    id,
+                    line_num: i.num,
+                    line: i.code.clone(),
+                    name: None,
+                });
+            }
+        };
+        // value of the last A-instruction, None after a label
+        let mut last_a: Option<&str> = None;
+        let mut after_jump = false;
+        let mut rom = 0;
+        for i in &instructions {
+            match i.t {
+                CommandType::LCommand => {
+                    last_a = None;
+                    after_jump = false;
+                    continue;
+                }
+                // a directive is linted as one unit, e.g. the entries of a .table
+                _ if after_jump && !i.expanded && !targets.contains(&rom) => {
+                    report(i, "L002");
+                    after_jump = false;
+                }
+                _ => after_jump = false,
+            }
+            rom += 1;
+            if i.t == CommandType::ACommand {
+                last_a = Some(&i.code[1..]);
+                continue;
+            }
+            let (dest, _, jump) = Strutil::c_fields(&i.code);
+            if dest.contains('A') && dest.contains('M') {
+                report(i, "L001");
+            }
+            if !jump.is_empty() {
+                if let Some(a) = last_a {
+                    let number = a.starts_with(|c: char| c.is_ascii_digit());
+                    if !number && !isa.symbols.contains_key(a) && !labels.contains(a) {
+                        report(i, "L003");
+                    }
+                }
+                after_jump = jump == "JMP";
+            }
+            if dest.contains('M') {
+                if let Some(a) = last_a {
+                    let kbd = isa.symbols.get("KBD").map(|n| n.to_string());
+                    if a == "KBD" || Some(a.to_string()) == kbd {
+                        report(i, "L004");
+                    }
+                }
+            }
+            if dest.contains('A') {
+                last_a = None;
+            }
+        }
+        ret.extend(Linter::uninitialized(&instructions, isa, uninit));
+        ret.sort_by_key(|l| l.line_num);
+        Ok(ret)
+    }
+
+    fn uninitialized(instructions: &[Instruction], isa: &Isa, uninit: &[String]) -> Vec<Lint> {
+        let mut code = Vec::new();
+        let mut labels = BTreeMap::new();
+        for i in instructions {
+            match i.t {
+                CommandType::LCommand => {
+                    labels.insert(i.code[1..i.code.len() - 1].to_owned(), code.len());
+                }
+                _ => code.push((i.num, i.code.clone())),
+            }
+        }
+        let cfg = Cfg::build(&code, &labels, &isa.symbols);
+        Dataflow::uninitialized(&cfg, &isa.symbols, uninit)
+            .into_iter()
+            .filter_map(|r| {
+                let id = if r.name == "D" || r.name == "A" {
+                    "L006"
+                } else {
+                    "L005"
+                };
+                let i = instructions.iter().find(|i| i.num == r.line_num)?;
+                if i.allow.iter().any(|a| a == id) {
+                    return None;
+                }
+                Some(Lint {
+                    id,
+                    line_num: r.line_num,
+                    line: r.line,
+                    name: Some(r.name),
+                })
+            })
+            .collect()
+    }
+
+    /// ROM addresses jumped to through `@n` rather than a label.
+    fn numeric_targets(instructions: &[Instruction]) -> HashSet<usize> {
+        let code: Vec<&Instruction> = instructions
+            .iter()
+            .filter(|i| i.t != CommandType::LCommand)
+            .collect();
+        code.windows(2)
+            .filter(|w| {
+                w[0].t == CommandType::ACommand && !Strutil::c_fields(&w[1].code).2.is_empty()
+            })
+            .filter_map(|w| w[0].code[1..].parse::<usize>().ok())
+            .collect()
+    }
+
+    fn instructions(source: &str, dir: &Path, isa: &Isa) -> Result<Vec<Instruction>, Box<HackError>> {
+        let mut ret = Vec::new();
+        let mut allow_next: Vec<String> = Vec::new();
+        for (num, raw) in Include::new().lines(source, dir)? {
+            let (code, comment) = Strutil::split_comment(&raw);
+            let mut allow = Linter::allowed(comment);
+            if code.is_empty() {
+                allow_next.extend(allow);
+                continue;
+            }
+            allow.append(&mut allow_next);
+            let lines = if Directive::is_check(code) {
+                Vec::new()
+            } else if Directive::is_directive(code) {
+                Directive::expand(num, code, &isa.symbols)?
+            } else {
+                vec![code.split_whitespace().collect::<String>()]
+            };
+            for (n, l) in lines.into_iter().enumerate() {
+                ret.push(Instruction {
+                    num,
+                    t: HPU::command_type(&l),
+                    code: l,
+                    allow: allow.clone(),
+                    expanded: n > 0,
+                });
+            }
+        }
+        Ok(ret)
+    }
+
+    fn allowed(comment: Option<&str>) -> Vec<String> {
+        let comment = match comment.and_then(|c| c.find("lint: allow(").map(|i| &c[i + 12..])) {
+            Some(c) => c,
+            None => return Vec::new(),
+        };
+        let end = comment.find(')').unwrap_or(comment.len());
+        comment[..end]
+            .split(',')
+            .map(|id| id.trim().to_owned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(source: &str) -> Vec<(&'static str, usize)> {
+        Linter::lint(source, Path::new("."), &Isa::hack(), &[])
+            .unwrap()
+            .iter()
+            .map(|l| (l.id, l.line_num))
+            .collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(ids("@SP\nAM=M+1\n"), vec![("L001", 1)]);
+        assert_eq!(ids("@SP\nA=M\nD=M\n"), vec![]);
+        assert_eq!(ids("@END\n0;JMP\nD=0\n(END)\nD=1\n"), vec![("L002", 2)]);
+        assert_eq!(ids("@2\n0;JMP\nD=0\n@2\n0;JMP\n"), vec![]);
+        assert_eq!(ids("@ret\n0;JMP\n"), vec![("L003", 1)]);
+        assert_eq!(ids("@LOOP\n0;JMP\n(LOOP)\n@R15\nA=M\n0;JMP\n"), vec![]);
+        assert_eq!(ids("@KBD\nM=0\n@24576\nD=M\n"), vec![("L004", 1)]);
+    }
+
+    #[test]
+    fn test_directives() {
+        let call = "@BACK\nD=A\n@R15\nM=D\n@2\nD=A\n@T\n0;JMP\n(BACK)\n@BACK\n0;JMP\n";
+        assert_eq!(ids(&format!("{}.table T 10, 20, 30\n", call)), vec![]);
+        // code after the table still is unreachable
+        assert_eq!(
+            ids(&format!("{}.table T 10, 20, 30\nD=0\n", call)),
+            vec![("L002", 12)]
+        );
+    }
+
+    #[test]
+    fn test_uninitialized() {
+        assert_eq!(ids("@sum\nD=M\n"), vec![("L005", 1)]);
+        assert_eq!(ids("@sum\nM=0\n@sum\nD=M\n"), vec![]);
+        assert_eq!(ids("@i\nM=D\n"), vec![("L006", 1)]);
+        assert_eq!(ids("@i\nM=D // lint: allow(L006)\n"), vec![]);
+        let lints = Linter::lint("@R0\nD=M\n@R2\nM=D+M\n", Path::new("."), &Isa::hack(), &["R2".into()]).unwrap();
+        assert_eq!(
+            format!("{}", lints[0]),
+            "[3]: M=D+M: L005 reads a variable not assigned on every path: R2"
+        );
+    }
+
+    #[test]
+    fn test_allow() {
+        assert_eq!(ids("@SP\nAM=M+1 // lint: allow(L001)\n"), vec![]);
+        assert_eq!(ids("@SP\n// lint: allow(L002, L001)\nAM=M+1\n"), vec![]);
+        assert_eq!(ids("@SP\nAM=M+1 // lint: allow(L004)\n"), vec![("L001", 1)]);
+    }
+
+    #[test]
+    fn test_projects() {
+        for name in [
+            "../max/Max.asm",
+            "../rect/Rect.asm",
+            "../../04/fill/Fill.asm",
+        ]
+        .iter()
+        {
+            let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(name);
+            let source = std::fs::read_to_string(path).unwrap();
+            assert_eq!(Linter::lint(&source, Path::new("."), &Isa::hack(), &[]).unwrap(), vec![]);
+        }
+    }
+
+    #[test]
+    fn test_mult() {
+        // s is never set when R1 is 0, Mult.tst presets R2 to -1
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../../04/mult/mult.asm");
+        let source = std::fs::read_to_string(path).unwrap();
+        let lints = Linter::lint(&source, Path::new("."), &Isa::hack(), &["R2".into()]).unwrap();
+        let found: Vec<(&str, usize, Option<&str>)> = lints
+            .iter()
+            .map(|l| (l.id, l.line_num, l.name.as_deref()))
+            .collect();
+        assert_eq!(found, vec![("L005", 66, Some("s"))]);
+    }
+}
@@ -0,0 +1,488 @@
+use super::analysis::{column, Analysis, Diagnostic, Occurrence};
+use super::isa::Isa;
+use super::strutil::Strutil;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/**
+ * Language server
+ *
+ * Speaks the Language Server Protocol over any reader and writer, the
+ * hack-lsp binary runs it on stdio. Documents are synced whole; every
+ * change reassembles the document in memory, see `Analysis`, whose
+ * diagnostics are published and whose addresses and encodings answer
+ * hover, definition and references.
+ */
+pub struct Server {
+    isa: Isa,
+    documents: HashMap<String, (String, Analysis)>, // uri -> (text, analysis)
+}
+
+impl Server {
+    pub fn new(isa: Isa) -> Server {
+        Server {
+            isa,
+            documents: HashMap::new(),
+        }
+    }
+
+    /// Reads one message, None at the end of the input.
+    pub fn read_message(input: &mut impl BufRead) -> std::io::Result<Option<Value>> {
+        match Server::read_body(input)? {
+            Some(body) => serde_json::from_slice(&body)
+                .map(Some)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the body of one message, None at the end of the input.
+    fn read_body(input: &mut impl BufRead) -> std::io::Result<Option<Vec<u8>>> {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if input.read_line(&mut header)? == 0 {
+                return Ok(None);
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(n) = header.strip_prefix("Content-Length:") {
+                length = n.trim().parse::<usize>().ok();
+            }
+        }
+        let length = length.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "missing Content-Length")
+        })?;
+        let mut body = vec![0; length];
+        input.read_exact(&mut body)?;
+        Ok(Some(body))
+    }
+
+    pub fn write_message(output: &mut impl Write, message: &Value) -> std::io::Result<()> {
+        let body = message.to_string();
+        write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        output.flush()
+    }
+
+    /// Answers messages until `exit` or the end of the input.
+    pub fn serve(
+        &mut self,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> std::io::Result<()> {
+        while let Some(body) = Server::read_body(input)? {
+            // a body that is not JSON gets an error, the next one may be fine
+            let message: Value = match serde_json::from_slice(&body) {
+                Ok(message) => message,
+                Err(e) => {
+                    let error = json!({
+                        "jsonrpc": "2.0",
+                        "id": null,
+                        "error": { "code": -32700, "message": format!("Parse error: {}", e) },
+                    });
+                    Server::write_message(output, &error)?;
+                    continue;
+                }
+            };
+            if message["method"] == "exit" {
+                break;
+            }
+            for reply in self.handle(&message) {
+                Server::write_message(output, &reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Responses and notifications for one message.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or("")
+            .to_owned();
+        let result = match message["method"].as_str().unwrap_or("") {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "completionProvider": { "triggerCharacters": ["@", "=", ";"] },
+                },
+                "serverInfo": { "name": "hack-lsp" },
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                return vec![self.update(uri, text.to_owned())];
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                match changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    Some(text) => return vec![self.update(uri, text.to_owned())],
+                    None => return Vec::new(),
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![Server::publish(&uri, &[])];
+            }
+            "textDocument/hover" => self.hover(&uri, &params["position"]),
+            "textDocument/definition" => self.definition(&uri, &params["position"]),
+            "textDocument/references" => {
+                let declaration = params["context"]["includeDeclaration"].as_bool();
+                self.references(&uri, &params["position"], declaration.unwrap_or(true))
+            }
+            "textDocument/completion" => self.completion(&uri, &params["position"]),
+            method => {
+                if message.get("id").is_none() {
+                    return Vec::new();
+                }
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "error": { "code": -32601, "message": format!("{} is not supported", method) },
+                })];
+            }
+        };
+        match message.get("id") {
+            Some(id) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+            None => Vec::new(),
+        }
+    }
+
+    fn update(&mut self, uri: String, text: String) -> Value {
+        // includes are relative to the document, file:///a/b/Foo.asm reads from /a/b
+        let path = Server::path(&uri);
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let analysis = Analysis::new(&text, dir, &self.isa);
+        let notification = Server::publish(&uri, &analysis.diagnostics);
+        self.documents.insert(uri, (text, analysis));
+        notification
+    }
+
+    /// The file a file:// URI names, with %XX escapes decoded.
+    fn path(uri: &str) -> PathBuf {
+        let bytes = uri.strip_prefix("file://").unwrap_or("").as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .and_then(|h| std::str::from_utf8(h).ok())
+                .and_then(|h| u8::from_str_radix(h, 16).ok());
+            match hex {
+                Some(b) if bytes[i] == b'%' => {
+                    decoded.push(b);
+                    i += 3;
+                }
+                _ => {
+                    decoded.push(bytes[i]);
+                    i += 1;
+                }
+            }
+        }
+        PathBuf::from(String::from_utf8_lossy(&decoded).into_owned())
+    }
+
+    fn publish(uri: &str, diagnostics: &[Diagnostic]) -> Value {
+        let diagnostics: Vec<Value> = diagnostics
+            .iter()
+            .map(|d| {
+                json!({
+                    "range": Server::range(d.line, d.start, d.end),
+                    "severity": if d.error { 1 } else { 2 },
+                    "code": d.code,
+                    "source": "hack",
+                    "message": d.message,
+                })
+            })
+            .collect();
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        })
+    }
+
+    fn range(line: usize, start: usize, end: usize) -> Value {
+        json!({
+            "start": { "line": line, "character": start },
+            "end": { "line": line, "character": end },
+        })
+    }
+
+    fn location(uri: &str, o: &Occurrence) -> Value {
+        json!({ "uri": uri, "range": Server::range(o.line, o.start, o.end) })
+    }
+
+    fn position(position: &Value) -> (usize, usize) {
+        let get = |k: &str| position[k].as_u64().unwrap_or(0) as usize;
+        (get("line"), get("character"))
+    }
+
+    fn hover(&self, uri: &str, position: &Value) -> Value {
+        let (line, character) = Server::position(position);
+        let analysis = match self.documents.get(uri) {
+            Some((_, analysis)) => analysis,
+            None => return Value::Null,
+        };
+        let mut lines = Vec::new();
+        if let Some(o) = analysis.symbol_at(line, character) {
+            let name = &o.name;
+            if let Some(n) = self.isa.symbols.get(name) {
+                lines.push(format!("`{}`: predefined symbol, {}", name, n));
+            } else if let Some(n) = analysis.variables.get(name) {
+                lines.push(format!("`{}`: variable, RAM address {}", name, n));
+            } else if let Some(n) = analysis.labels.get(name) {
+                lines.push(format!("`{}`: label, ROM address {}", name, n));
+            }
+        }
+        if let Some(words) = analysis.words.get(&line) {
+            lines.push(format!("```\n{}\n```", words.join("\n")));
+        }
+        if lines.is_empty() {
+            return Value::Null;
+        }
+        json!({ "contents": { "kind": "markdown", "value": lines.join("\n\n") } })
+    }
+
+    fn definition(&self, uri: &str, position: &Value) -> Value {
+        let (line, character) = Server::position(position);
+        let analysis = match self.documents.get(uri) {
+            Some((_, analysis)) => analysis,
+            None => return Value::Null,
+        };
+        analysis
+            .symbol_at(line, character)
+            .and_then(|o| analysis.definition(&o.name))
+            .map_or(Value::Null, |o| Server::location(uri, o))
+    }
+
+    fn references(&self, uri: &str, position: &Value, declaration: bool) -> Value {
+        let (line, character) = Server::position(position);
+        let analysis = match self.documents.get(uri) {
+            Some((_, analysis)) => analysis,
+            None => return Value::Null,
+        };
+        let name = match analysis.symbol_at(line, character) {
+            Some(o) => &o.name,
+            None => return Value::Null,
+        };
+        let locations: Vec<Value> = analysis
+            .occurrences
+            .iter()
+            .filter(|o| o.name == *name && (declaration || !o.declaration))
+            .map(|o| Server::location(uri, o))
+            .collect();
+        Value::Array(locations)
+    }
+
+    /// Symbols after `@`, jumps after `;`, computations after `=`, else both fields.
+    fn completion(&self, uri: &str, position: &Value) -> Value {
+        let (line, character) = Server::position(position);
+        let (text, analysis) = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Value::Null,
+        };
+        let raw = text.lines().nth(line).unwrap_or("");
+        let end = raw
+            .char_indices()
+            .map(|(i, _)| i)
+            .find(|i| column(raw, *i) >= character)
+            .unwrap_or(raw.len());
+        let typed = Strutil::split_comment(&raw[..end]).0;
+        // (names, kind as numbered by LSP, detail)
+        let tables: Vec<(Vec<&String>, u8, &str)> = if typed.starts_with('@') {
+            vec![
+                (self.isa.symbols.keys().collect(), 21, "predefined symbol"),
+                (analysis.labels.keys().collect(), 18, "label"),
+                (analysis.variables.keys().collect(), 6, "variable"),
+            ]
+        } else if typed.contains(';') {
+            vec![(self.isa.jump.keys().collect(), 14, "jump")]
+        } else if typed.contains('=') {
+            vec![(self.isa.comp.keys().collect(), 14, "comp")]
+        } else {
+            vec![
+                (self.isa.dest.keys().collect(), 14, "dest"),
+                (self.isa.comp.keys().collect(), 14, "comp"),
+            ]
+        };
+        let mut items: Vec<(&str, u8, &str)> = tables
+            .iter()
+            .flat_map(|(names, kind, detail)| {
+                names.iter().map(move |n| (n.as_str(), *kind, *detail))
+            })
+            .collect();
+        items.sort_unstable();
+        items.dedup_by(|a, b| a.0 == b.0);
+        let items: Vec<Value> = items
+            .iter()
+            .map(|(label, kind, detail)| json!({ "label": label, "kind": kind, "detail": detail }))
+            .collect();
+        Value::Array(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "// counts n down from 10\n\
+                           @10\n\
+                           D=A\n\
+                           @n\n\
+                           M=D\n\
+                           (LOOP)\n\
+                           \x20 @n\n\
+                           \x20 MD=M-1\n\
+                           \x20 @LOOP\n\
+                           \x20 D;JGT\n\
+                           (END)\n\
+                           \x20 @END\n\
+                           \x20 0;JMP\n";
+
+    /// Frames `messages` the way a client would and parses the replies.
+    fn session(messages: &[Value]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for m in messages {
+            Server::write_message(&mut input, m).unwrap();
+        }
+        let mut output = Vec::new();
+        Server::new(Isa::hack())
+            .serve(&mut &input[..], &mut output)
+            .unwrap();
+        let mut replies = Vec::new();
+        let mut output = &output[..];
+        while let Some(reply) = Server::read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        replies
+    }
+
+    fn request(id: u64, method: &str, line: usize, character: usize) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": "file:///Count.asm" },
+                "position": { "line": line, "character": character },
+                "context": { "includeDeclaration": false },
+            },
+        })
+    }
+
+    #[test]
+    fn test_path() {
+        assert_eq!(
+            Server::path("file:///home/a%20b/caf%C3%A9/Foo.asm"),
+            PathBuf::from("/home/a b/café/Foo.asm")
+        );
+        assert_eq!(
+            Server::path("file:///a/100%/x%2"),
+            PathBuf::from("/a/100%/x%2")
+        );
+        assert_eq!(Server::path("untitled:Foo"), PathBuf::from(""));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let mut input = b"Content-Length: 5\r\n\r\n{oops".to_vec();
+        let initialize = json!({ "jsonrpc": "2.0", "id": 0, "method": "initialize" });
+        Server::write_message(&mut input, &initialize).unwrap();
+        let mut output = Vec::new();
+        Server::new(Isa::hack())
+            .serve(&mut &input[..], &mut output)
+            .unwrap();
+        let mut output = &output[..];
+        let error = Server::read_message(&mut output).unwrap().unwrap();
+        assert_eq!(error["error"]["code"], -32700);
+        assert_eq!(error["id"], Value::Null);
+        let reply = Server::read_message(&mut output).unwrap().unwrap();
+        assert_eq!(reply["id"], 0);
+    }
+
+    #[test]
+    fn test_session() {
+        let open = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {
+                "textDocument": { "uri": "file:///Count.asm", "languageId": "hack", "version": 1, "text": PROGRAM },
+            },
+        });
+        let change = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": "file:///Count.asm", "version": 2 },
+                "contentChanges": [{ "text": format!("{}D=Q;J\n", PROGRAM) }],
+            },
+        });
+        let replies = session(&[
+            json!({ "jsonrpc": "2.0", "id": 0, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            open,
+            request(1, "textDocument/hover", 8, 4),
+            request(2, "textDocument/hover", 3, 1),
+            request(3, "textDocument/definition", 8, 4),
+            request(4, "textDocument/references", 3, 1),
+            change,
+            request(5, "textDocument/completion", 13, 5),
+            request(6, "textDocument/formatting", 0, 0),
+            json!({ "jsonrpc": "2.0", "id": 7, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+            request(8, "textDocument/hover", 8, 4),
+        ]);
+        assert_eq!(replies.len(), 10);
+        assert_eq!(replies[0]["result"]["capabilities"]["hoverProvider"], true);
+        assert_eq!(replies[1]["params"]["diagnostics"], json!([]));
+        assert_eq!(
+            replies[2]["result"]["contents"]["value"],
+            "`LOOP`: label, ROM address 4\n\n```\n0000000000000100\n```"
+        );
+        assert_eq!(
+            replies[3]["result"]["contents"]["value"],
+            "`n`: variable, RAM address 16\n\n```\n0000000000010000\n```"
+        );
+        assert_eq!(
+            replies[4]["result"]["range"],
+            json!({ "start": { "line": 5, "character": 1 }, "end": { "line": 5, "character": 5 } })
+        );
+        let lines: Vec<&Value> = replies[5]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|l| &l["range"]["start"]["line"])
+            .collect();
+        assert_eq!(lines, vec![3, 6]);
+        let diagnostics = &replies[6]["params"]["diagnostics"];
+        assert_eq!(diagnostics[0]["code"], "E003");
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 13);
+        let labels: Vec<&str> = replies[7]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| i["label"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            labels,
+            vec!["JEQ", "JGE", "JGT", "JLE", "JLT", "JMP", "JNE"]
+        );
+        assert_eq!(replies[8]["error"]["code"], -32601);
+        assert_eq!(
+            replies[9],
+            json!({ "jsonrpc": "2.0", "id": 7, "result": null })
+        );
+    }
+}
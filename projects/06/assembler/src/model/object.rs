@@ -0,0 +1,231 @@
+use super::assembler::create_assembler;
+use super::base::ROM_SIZE;
+use super::dialect::Dialect;
+use super::error::*;
+use super::include::STD;
+use super::isa::Isa;
+use crate::hack_report_less;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/**
+ * Relocatable objects
+ *
+ * `hack -c Foo.asm` writes Foo.hobj, the program with its addresses left
+ * open: one slot per ROM word, either a finished word, an A-instruction
+ * loading an offset into the object's own code (a relocation), or an
+ * A-instruction loading a symbol the object does not define. Labels are
+ * local unless named by `.export`. The linker places the objects one
+ * after the other, resolves external symbols against the exports and
+ * allocates whatever is left as variables, once for the whole program.
+ *
+ * A library bundles objects by name; a member is linked only when it
+ * exports a symbol the program still needs. Both are stored as JSON.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Slot {
+    Word(String),
+    Relocate(usize),
+    External(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Object {
+    pub code: Vec<Slot>,
+    #[serde(default)]
+    pub exports: BTreeMap<String, usize>, // label -> offset into code
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Library {
+    pub members: BTreeMap<String, Object>,
+}
+
+fn read(path: &Path) -> Result<String, Box<HackError>> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(s),
+        Err(e) => hack_report_less!("E008", format!("Could not read {}: {}", path.display(), e)),
+    }
+}
+
+fn write(path: &Path, json: String) -> Result<(), Box<HackError>> {
+    if let Err(e) = std::fs::write(path, json + "\n") {
+        hack_report_less!("E008", format!("Could not write {}: {}", path.display(), e))
+    }
+    Ok(())
+}
+
+impl Object {
+    /// Symbols the object loads but does not define.
+    pub fn externals(&self) -> impl Iterator<Item = &str> {
+        self.code.iter().filter_map(|s| match s {
+            Slot::External(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Object, Box<HackError>> {
+        let object: Object = match serde_json::from_str(&read(path)?) {
+            Ok(object) => object,
+            Err(e) => hack_report_less!(
+                "E018",
+                format!("Malformed object {}: {}", path.display(), e)
+            ),
+        };
+        if let Err(e) = object.validate() {
+            hack_report_less!(
+                "E018",
+                format!("Malformed object {}: {}", path.display(), e)
+            )
+        }
+        Ok(object)
+    }
+
+    /// Checks what the linker relies on but JSON cannot express: words are
+    /// 16 binary digits, offsets stay within the code and fit an A-instruction.
+    fn validate(&self) -> Result<(), String> {
+        let offset = |what: String, offset: usize| {
+            if offset >= ROM_SIZE {
+                Err(format!("{} {} is past the end of ROM", what, offset))
+            } else if offset > self.code.len() {
+                Err(format!(
+                    "{} {} is past the end of the code, {} words",
+                    what,
+                    offset,
+                    self.code.len()
+                ))
+            } else {
+                Ok(())
+            }
+        };
+        for (i, slot) in self.code.iter().enumerate() {
+            match slot {
+                Slot::Word(word) => {
+                    if word.len() != 16 || !word.bytes().all(|b| b == b'0' || b == b'1') {
+                        return Err(format!("word {} is not 16 binary digits: {:?}", i, word));
+                    }
+                }
+                Slot::Relocate(n) => offset(format!("word {} relocates to", i), *n)?,
+                Slot::External(_) => {}
+            }
+        }
+        for (label, n) in &self.exports {
+            offset(format!("export {} at", label), *n)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<HackError>> {
+        write(path, serde_json::to_string_pretty(self).unwrap())
+    }
+}
+
+impl Library {
+    /// The standard library as objects, one member per routine, e.g. `mult`.
+    pub fn std(isa: &Isa) -> Result<Library, Box<HackError>> {
+        let mut library = Library::default();
+        for (name, _) in STD.iter() {
+            let path = Path::new(name);
+            let mut assembler = create_assembler(path, isa.clone(), Dialect::Strict);
+            assembler.set_verbose(false);
+            assembler.set_source(&format!(".include <{}>\n", name));
+            let stem = path.file_stem().unwrap().to_string_lossy().into_owned();
+            library.members.insert(stem, assembler.object()?);
+        }
+        Ok(library)
+    }
+
+    pub fn load(path: &Path) -> Result<Library, Box<HackError>> {
+        let library: Library = match serde_json::from_str(&read(path)?) {
+            Ok(library) => library,
+            Err(e) => hack_report_less!(
+                "E018",
+                format!("Malformed library {}: {}", path.display(), e)
+            ),
+        };
+        for (name, member) in &library.members {
+            if let Err(e) = member.validate() {
+                hack_report_less!(
+                    "E018",
+                    format!(
+                        "Malformed library {}: member {}: {}",
+                        path.display(),
+                        name,
+                        e
+                    )
+                )
+            }
+        }
+        Ok(library)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<HackError>> {
+        write(path, serde_json::to_string_pretty(self).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let object = Object {
+            code: vec![
+                Slot::External("MULT".into()),
+                Slot::Word("1110101010000111".into()),
+                Slot::Relocate(0),
+            ],
+            exports: vec![("MAIN".to_owned(), 0)].into_iter().collect(),
+        };
+        let json = serde_json::to_string(&object).unwrap();
+        assert_eq!(
+            json,
+            r#"{"code":[{"external":"MULT"},{"word":"1110101010000111"},{"relocate":0}],"exports":{"MAIN":0}}"#
+        );
+        assert_eq!(serde_json::from_str::<Object>(&json).unwrap(), object);
+        assert_eq!(object.externals().collect::<Vec<_>>(), vec!["MULT"]);
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir().join(format!("hack-{}-object", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Foo.hobj");
+        let good = r#"{"code":[{"word":"1110101010000111"},{"relocate":2}],"exports":{"END":2}}"#;
+        std::fs::write(&path, good).unwrap();
+        assert_eq!(Object::load(&path).unwrap().code.len(), 2);
+        for bad in [
+            r#"{"code":[{"word":"111010101000011"}]}"#,
+            r#"{"code":[{"word":"111010101000011x"}]}"#,
+            r#"{"code":[{"relocate":3},{"relocate":0}]}"#,
+            r#"{"code":[],"exports":{"MAIN":1}}"#,
+            r#"{"code":[{"relocate":32768}]}"#,
+        ]
+        .iter()
+        {
+            std::fs::write(&path, bad).unwrap();
+            assert_eq!(Object::load(&path).unwrap_err().code, "E018", "{}", bad);
+        }
+
+        let path = dir.join("Foo.hlib");
+        std::fs::write(&path, format!(r#"{{"members":{{"foo":{}}}}}"#, good)).unwrap();
+        assert!(Library::load(&path).is_ok());
+        std::fs::write(&path, r#"{"members":{"foo":{"code":[{"word":"2"}]}}}"#).unwrap();
+        let e = Library::load(&path).unwrap_err();
+        assert_eq!(e.code, "E018");
+        assert!(e.comment.contains("member foo"));
+    }
+
+    #[test]
+    fn test_std() {
+        let library = Library::std(&Isa::default()).unwrap();
+        assert_eq!(library.members.len(), STD.len());
+        for (name, member) in &library.members {
+            assert_eq!(member.exports.get(&format!("std.{}", name)), Some(&0));
+            assert!(member.externals().all(|s| s.starts_with("std.")));
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use super::base::*;
+use super::directive::*;
+use super::hpu::HPU;
+use super::isa::Isa;
+use super::strutil::Strutil;
+use std::collections::HashSet;
+
+/**
+ * Peephole optimizer
+ *
+ * Works on the instruction stream after directive expansion, labels stay
+ * symbolic so the first pass resolves them again afterwards. Repeated until
+ * nothing changes:
+ *
+ * unreachable code    instructions between an unconditional jump and the next label
+ * jump to next        `@L`, `0;JMP` right before `(L)`, the `@L` stays unless
+ *                     the code at L sets A before it uses A or M
+ * dead A-instruction  `@x` immediately followed by another A-instruction
+ * repeated load       `@x` while A already holds x since the last label
+ *
+ * A jump target written as a number, `@95` before `0;JMP`, becomes a label
+ * first so it follows the code it points to. Numbers used as ROM addresses
+ * anywhere else, like symbol-less return addresses, are not recognized.
+ */
+pub struct Optimizer {}
+
+impl Optimizer {
+    pub fn optimize(lines: Vec<(usize, String)>, isa: &Isa) -> Vec<(usize, String)> {
+        let mut lines = Optimizer::label_numeric_targets(lines);
+        loop {
+            let before = lines.len();
+            lines = Optimizer::unreachable(lines);
+            lines = Optimizer::jump_to_next(lines);
+            lines = Optimizer::dead_loads(lines);
+            lines = Optimizer::repeated_loads(lines, isa);
+            if lines.len() == before {
+                return lines;
+            }
+        }
+    }
+
+    /// Whether `l` occupies a ROM word.
+    pub fn is_code(l: &str) -> bool {
+        !l.is_empty()
+            && !Directive::is_directive(l)
+            && HPU::command_type(l) != CommandType::LCommand
+    }
+
+    fn jump(l: &str) -> &str {
+        if HPU::command_type(l) == CommandType::CCommand {
+            Strutil::c_fields(l).2
+        } else {
+            ""
+        }
+    }
+
+    fn label_numeric_targets(lines: Vec<(usize, String)>) -> Vec<(usize, String)> {
+        let code: Vec<usize> = (0..lines.len())
+            .filter(|i| Optimizer::is_code(&lines[*i].1))
+            .collect();
+        let mut rewrite = HashSet::new();
+        let mut targets = HashSet::new();
+        for w in code.windows(2) {
+            let n = lines[w[0]]
+                .1
+                .strip_prefix('@')
+                .and_then(|v| v.parse::<usize>().ok());
+            if let Some(n) = n {
+                if !Optimizer::jump(&lines[w[1]].1).is_empty() {
+                    rewrite.insert(w[0]);
+                    targets.insert(n);
+                }
+            }
+        }
+        let mut ret = Vec::new();
+        let mut rom = 0;
+        for (i, (num, l)) in lines.into_iter().enumerate() {
+            let code = Optimizer::is_code(&l);
+            if code && targets.contains(&rom) {
+                ret.push((num, format!("(__ROM_{})", rom)));
+            }
+            if rewrite.contains(&i) {
+                ret.push((num, format!("@__ROM_{}", &l[1..])));
+            } else {
+                ret.push((num, l));
+            }
+            if code {
+                rom += 1;
+            }
+        }
+        if targets.contains(&rom) {
+            let num = ret.last().map_or(0, |(num, _)| *num);
+            ret.push((num, format!("(__ROM_{})", rom)));
+        }
+        ret
+    }
+
+    fn unreachable(lines: Vec<(usize, String)>) -> Vec<(usize, String)> {
+        let mut after_jump = false;
+        let mut ret = Vec::new();
+        for (num, l) in lines {
+            if HPU::command_type(&l) == CommandType::LCommand {
+                after_jump = false;
+            } else if after_jump && Optimizer::is_code(&l) {
+                continue;
+            }
+            after_jump = after_jump || Optimizer::jump(&l) == "JMP";
+            ret.push((num, l));
+        }
+        ret
+    }
+
+    fn jump_to_next(lines: Vec<(usize, String)>) -> Vec<(usize, String)> {
+        let mut removed = vec![false; lines.len()];
+        let code: Vec<usize> = (0..lines.len())
+            .filter(|i| Optimizer::is_code(&lines[*i].1))
+            .collect();
+        for w in code.windows(2) {
+            let (a, c) = (&lines[w[0]].1, &lines[w[1]].1);
+            let target = match a.strip_prefix('@') {
+                Some(t) => t,
+                None => continue,
+            };
+            if Optimizer::jump(c).is_empty() || !Strutil::c_fields(c).0.is_empty() {
+                continue;
+            }
+            let next = lines[w[1] + 1..]
+                .iter()
+                .take_while(|(_, l)| !Optimizer::is_code(l))
+                .any(|(_, l)| *l == format!("({})", target));
+            if next {
+                removed[w[0]] = !Optimizer::uses_a(&lines[w[1] + 1..]);
+                removed[w[1]] = true;
+            }
+        }
+        lines
+            .into_iter()
+            .zip(removed)
+            .filter(|(_, r)| !r)
+            .map(|(l, _)| l)
+            .collect()
+    }
+
+    /// Whether the code of `lines` reads A, as an operand, an M address or a
+    /// jump target, before it sets A. Labels are walked through.
+    fn uses_a(lines: &[(usize, String)]) -> bool {
+        for (_, l) in lines.iter().filter(|(_, l)| Optimizer::is_code(l)) {
+            if HPU::command_type(l) == CommandType::ACommand {
+                return false;
+            }
+            let (dest, comp, jump) = Strutil::c_fields(l);
+            if comp.contains('A') || comp.contains('M') || dest.contains('M') || !jump.is_empty() {
+                return true;
+            }
+            if dest.contains('A') {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn dead_loads(lines: Vec<(usize, String)>) -> Vec<(usize, String)> {
+        let mut ret: Vec<(usize, String)> = Vec::new();
+        for (num, l) in lines {
+            if l.starts_with('@') {
+                let last = ret.iter().rposition(|(_, r)| !Directive::is_directive(r));
+                if let Some(i) = last.filter(|i| ret[*i].1.starts_with('@')) {
+                    ret.remove(i);
+                }
+            }
+            ret.push((num, l));
+        }
+        ret
+    }
+
+    fn repeated_loads(lines: Vec<(usize, String)>, isa: &Isa) -> Vec<(usize, String)> {
+        let value = |v: &str| match isa.symbols.get(v) {
+            Some(n) => n.to_string(),
+            None => v.to_owned(),
+        };
+        let mut a: Option<String> = None;
+        let mut ret = Vec::new();
+        for (num, l) in lines {
+            match HPU::command_type(&l) {
+                _ if Directive::is_directive(&l) => {}
+                CommandType::LCommand => a = None,
+                CommandType::ACommand => {
+                    let v = value(&l[1..]);
+                    if a.as_ref() == Some(&v) {
+                        continue;
+                    }
+                    a = Some(v);
+                }
+                CommandType::CCommand => {
+                    if Strutil::c_fields(&l).0.contains('A') {
+                        a = None;
+                    }
+                }
+            }
+            ret.push((num, l));
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn optimize(source: &str) -> String {
+        let lines = source
+            .lines()
+            .enumerate()
+            .map(|(n, l)| (n, l.to_owned()))
+            .collect();
+        let out: Vec<String> = Optimizer::optimize(lines, &Isa::hack())
+            .into_iter()
+            .map(|(_, l)| l)
+            .collect();
+        out.join("\n")
+    }
+
+    #[test]
+    fn test_peepholes() {
+        assert_eq!(optimize("@x\n@y\nM=0"), "@y\nM=0");
+        assert_eq!(optimize("@x\nM=0\n@x\nM=M+1"), "@x\nM=0\nM=M+1");
+        assert_eq!(optimize("@R1\nD=M\n@1\nM=D"), "@R1\nD=M\nM=D");
+        assert_eq!(optimize("@x\nA=M\n@x\nM=0"), "@x\nA=M\n@x\nM=0");
+        assert_eq!(optimize("@x\nM=0\n(L)\n@x\nM=0"), "@x\nM=0\n(L)\n@x\nM=0");
+        assert_eq!(optimize("@L\n0;JMP\n(L)\nD=0"), "(L)\nD=0");
+        assert_eq!(optimize("@L\nD;JGT\n(L)\nD=0"), "(L)\nD=0");
+        // the code at L addresses RAM[L], only the jump goes
+        assert_eq!(
+            optimize("@5\nD=A\n@L\nD;JGT\n(L)\nM=D"),
+            "@5\nD=A\n@L\n(L)\nM=D"
+        );
+        assert_eq!(
+            optimize("@L\n0;JMP\n(L)\nD=0\nD=D+A"),
+            "@L\n(L)\nD=0\nD=D+A"
+        );
+        assert_eq!(
+            optimize("@L\n0;JMP\n(L)\nD=0\nA=D\nM=0"),
+            "(L)\nD=0\nA=D\nM=0"
+        );
+        assert_eq!(optimize("@L\nM=D;JGT\n(L)\nD=0"), "@L\nM=D;JGT\n(L)\nD=0");
+        assert_eq!(
+            optimize("@END\n0;JMP\nD=1\n.warning \"w\"\n(L)\nD=0\n(END)\n@END\n0;JMP\nD=0"),
+            "@END\n0;JMP\n.warning \"w\"\n(L)\nD=0\n(END)\n@END\n0;JMP"
+        );
+        // removing the unreachable code turns the jump into one to the next instruction
+        assert_eq!(optimize("@L\n0;JMP\nD=1\n(L)\nD=0"), "(L)\nD=0");
+    }
+
+    #[test]
+    fn test_numeric_targets() {
+        assert_eq!(
+            optimize("@4\n0;JMP\nD=1\nD=0\nD=D+1\n@0\n0;JMP"),
+            "(__ROM_0)\n(__ROM_4)\nD=D+1\n@__ROM_0\n0;JMP"
+        );
+        assert_eq!(
+            optimize("(X)\n@0\n0;JMP"),
+            "(X)\n(__ROM_0)\n@__ROM_0\n0;JMP"
+        );
+    }
+}
@@ -1,8 +1,13 @@
 use super::base::*;
+use super::dialect::Dialect;
 use super::error::HackError;
+use super::isa::Isa;
 use crate::hack_report;
-use std::collections::HashMap;
-use std::vec::Vec;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 /**
  * Recursive Descent Parser
  *
@@ -20,14 +25,14 @@ use std::vec::Vec;
 #[derive(Debug)]
 pub struct ParserArg<'a> {
     pub parser: Option<&'a mut Parser>,
-    pub tokens: Option<Box<Vec<Token>>>,
+    pub tokens: Option<Vec<Token>>,
     pub index: Option<Box<usize>>,
     pub content: String,
     pub line_num: Option<Box<usize>>,
 }
 
-impl<'a> std::fmt::Display for ParserArg<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl<'a> core::fmt::Display for ParserArg<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         writeln!(
             f,
             "[{}]: {} -> tokens: {:?}",
@@ -72,7 +77,7 @@ impl ParserResult {
 
 impl<'a> ParserArg<'a> {
     pub fn advance(&mut self) {
-        let ref mut index = **self.index.as_mut().unwrap();
+        let index = &mut **self.index.as_mut().unwrap();
         *index += 1;
     }
     pub fn line_num(&self) -> usize {
@@ -82,9 +87,12 @@ impl<'a> ParserArg<'a> {
 
 #[derive(Debug)]
 pub struct Parser {
-    pub map: Option<HashMap<String, usize>>,
+    pub map: Option<BTreeMap<String, usize>>,
     pub varmem: Option<usize>, // variable memory
     pub result: Option<ParserResult>,
+    pub isa: Isa,
+    pub dialect: Dialect,
+    pub verbose: bool, // trace every parsed line, with std only
 }
 
 #[warn(unused_macros)]
@@ -93,12 +101,22 @@ macro_rules! create_expect {
         pub fn $func_name<'a>(
             parg: &'a mut ParserArg<'a>,
         ) -> Result<&'a mut ParserArg<'a>, Box<HackError>> {
-            match &parg.tokens.as_ref().unwrap()[**(parg.index.as_ref().unwrap())].token_type {
-                $x => {
+            let curr = **parg.index.as_ref().unwrap();
+            match parg.tokens.as_ref().unwrap().get(curr).map(|t| &t.token_type) {
+                Some($x) => {
                     parg.advance();
                     Ok(parg)
                 }
-                t => hack_report!(parg, format!("Expect {}, but got {:?}", stringify!($x), t)),
+                None => hack_report!(
+                    parg,
+                    "E002",
+                    format!("Expect {}, but the line ends", stringify!($x))
+                ),
+                Some(t) => hack_report!(
+                    parg,
+                    "E002",
+                    format!("Expect {}, but got {:?}", stringify!($x), t)
+                ),
             }
         }
     };
@@ -111,41 +129,35 @@ macro_rules! create_expect_predefined {
             parg: &'a mut ParserArg<'a>,
         ) -> Result<&'a mut ParserArg<'a>, Box<HackError>> {
             let tokens = parg.tokens.as_ref().unwrap();
-            //let parser = parg.parser.as_ref().unwrap();
+            let table = &parg.parser.as_ref().unwrap().isa.$x;
             let curr = **parg.index.as_ref().unwrap();
+            if curr == tokens.len() {
+                hack_report!(
+                    parg,
+                    "E002",
+                    format!("Expected {} but the line ends", stringify!($y))
+                )
+            }
             match tokens[curr].token_type {
                 $y => {
-                    if $x.contains_key(&tokens[curr].repr) {
+                    if table.contains_key(&tokens[curr].repr) {
                         parg.advance();
                         return Ok(parg);
                     }
-                    hack_report!(
-                        parg,
-                        format!(
-                            "{} is not defined in table {}!",
-                            &tokens[curr].repr,
-                            stringify!($x)
-                        )
-                    )
+                    Parser::report_undefined(parg, stringify!($x))
                 }
                 $(
                     $extra => {
-                        if $x.contains_key(&tokens[curr].repr) {
+                        if table.contains_key(&tokens[curr].repr) {
                             parg.advance();
                             return Ok(parg);
                         }
-                        hack_report!(
-                            parg,
-                            format!(
-                                "{} is not defined in table {}!",
-                                &tokens[curr].repr,
-                                stringify!($x)
-                            )
-                        )
+                        Parser::report_undefined(parg, stringify!($x))
                     }
                 )*
                 _ => hack_report!(
                     parg,
+                    "E002",
                     format!(
                         "Expected {} but found {:?}",
                         stringify!($y),
@@ -157,34 +169,46 @@ macro_rules! create_expect_predefined {
     };
 }
 
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
+}
+
 // 一次性解析一组 token
 // 返回结果给 HPU
 impl Parser {
     pub fn new() -> Parser {
         Parser {
-            map: Some(HashMap::new()),
-            varmem: Some(16),
+            map: Some(BTreeMap::new()),
+            varmem: Some(VARMEM_BASE),
             result: Some(ParserResult {
                 t: None,
                 ar: None,
                 cr: None,
                 lr: None,
             }),
+            isa: Isa::hack(),
+            dialect: Dialect::Strict,
+            verbose: true,
         }
     }
 
     pub fn parse_command<'a>(
         parg: &'a mut ParserArg<'a>,
-    ) -> Result<&'a mut ParserArg, Box<HackError>> {
-        println!("{}", parg);
+    ) -> Result<&'a mut ParserArg<'a>, Box<HackError>> {
+        #[cfg(feature = "std")]
+        if parg.parser.as_ref().unwrap().verbose {
+            println!("{}", parg);
+        }
         let tokens = parg.tokens.as_mut().unwrap();
         match tokens[0].token_type {
             TOKENTYPE::AT => {
-                return Parser::expect_a_command(parg);
+                Parser::expect_a_command(parg)
             }
             TOKENTYPE::LEFTBRACE => Parser::expect_l_command(parg),
             _ => {
-                return Parser::expect_c_command(parg);
+                Parser::expect_c_command(parg)
             }
         }
     }
@@ -193,15 +217,15 @@ impl Parser {
     create_expect!(expect_rightbrace, TOKENTYPE::RIGHTBRACE);
     create_expect!(expect_equal, TOKENTYPE::EQUAL);
     create_expect!(expect_semicolon, TOKENTYPE::SEMICOLON);
-    create_expect_predefined!(expect_ccmd_dest, DEST, TOKENTYPE::SYMBOL);
+    create_expect_predefined!(expect_ccmd_dest, dest, TOKENTYPE::SYMBOL);
     create_expect_predefined!(
         expect_ccmd_comp,
-        COMP,
+        comp,
         TOKENTYPE::EXPRESSION,
         TOKENTYPE::SYMBOL,
         TOKENTYPE::NUMBER
     );
-    create_expect_predefined!(expect_ccmd_jump, JUMP, TOKENTYPE::SYMBOL);
+    create_expect_predefined!(expect_ccmd_jump, jump, TOKENTYPE::SYMBOL);
 
     pub fn expect_l_command<'a>(
         parg: &'a mut ParserArg<'a>,
@@ -225,10 +249,26 @@ impl Parser {
         }
     }
 
+    fn report_undefined<'a>(
+        parg: &'a mut ParserArg<'a>,
+        table: &str,
+    ) -> Result<&'a mut ParserArg<'a>, Box<HackError>> {
+        let repr = &parg.tokens.as_ref().unwrap()[**parg.index.as_ref().unwrap()].repr;
+        let isa = &parg.parser.as_ref().unwrap().isa;
+        let mut comment = format!("{} is not defined in table {}!", repr, table.to_uppercase());
+        if let Some(s) = Dialect::suggest(table, repr, isa) {
+            comment.push_str(&format!(" Did you mean {}?", s));
+        }
+        hack_report!(parg, "E003", comment)
+    }
+
     pub fn expect_c_command<'a>(
         parg: &'a mut ParserArg<'a>,
     ) -> Result<&'a mut ParserArg<'a>, Box<HackError>> {
         let parser = parg.parser.as_mut().unwrap();
+        if parser.dialect == Dialect::Relaxed {
+            Dialect::normalize(parg.tokens.as_mut().unwrap(), &parser.isa);
+        }
         let result = parser.result.as_mut().unwrap();
         result.clear();
         result.t = Some(CommandType::CCommand);
@@ -249,19 +289,19 @@ impl Parser {
         let curr = **parg.index.as_ref().unwrap();
         let len = tokens.len();
         if curr == len {
-            return Ok(parg);
+            Ok(parg)
         } else if curr + 1 < len && tokens[curr + 1].token_type == TOKENTYPE::EQUAL {
             // this is a defect, but I have no time to fix the expect_* macro
             result.cr.as_mut().unwrap().dest = Some(tokens[curr].repr.clone());
-            return Parser::expect_ccmd_dest(parg)
+            Parser::expect_ccmd_dest(parg)
                 .and_then(Parser::expect_equal)
-                .and_then(Parser::expect_c_command_rec);
+                .and_then(Parser::expect_c_command_rec)
         } else if tokens[curr].token_type == TOKENTYPE::SEMICOLON {
-            result.cr.as_mut().unwrap().jump = Some(tokens[curr + 1].repr.clone());
-            return Parser::expect_semicolon(parg).and_then(Parser::expect_ccmd_jump);
+            result.cr.as_mut().unwrap().jump = tokens.get(curr + 1).map(|t| t.repr.clone());
+            Parser::expect_semicolon(parg).and_then(Parser::expect_ccmd_jump)
         } else {
             result.cr.as_mut().unwrap().comp = Some(tokens[curr].repr.clone());
-            return Parser::expect_ccmd_comp(parg).and_then(Parser::expect_c_command_rec);
+            Parser::expect_ccmd_comp(parg).and_then(Parser::expect_c_command_rec)
         }
     }
 
@@ -271,10 +311,10 @@ impl Parser {
         parg.advance();
         //look ahead
         let tokens = parg.tokens.as_ref().unwrap();
-        match tokens[1].token_type {
-            TOKENTYPE::NUMBER => Parser::expect_number(parg),
-            TOKENTYPE::SYMBOL => Parser::expect_symbol_va(parg),
-            _ => hack_report!(parg, "Illegal A command"),
+        match tokens.get(1).map(|t| &t.token_type) {
+            Some(TOKENTYPE::NUMBER) => Parser::expect_number(parg),
+            Some(TOKENTYPE::SYMBOL) => Parser::expect_symbol_va(parg),
+            _ => hack_report!(parg, "E004", "Illegal A command"),
         }
     }
 
@@ -286,16 +326,27 @@ impl Parser {
         let parser = parg.parser.as_mut().unwrap();
         match tokens[curr].token_type {
             TOKENTYPE::NUMBER => {
+                // the constant is loaded into 15 bits
+                if !matches!(tokens[curr].repr.parse::<u16>(), Ok(n) if n < 32768) {
+                    hack_report!(
+                        parg,
+                        "E004",
+                        format!(
+                            "{} does not fit in 15 bits, the largest constant is 32767",
+                            tokens[curr].repr
+                        )
+                    )
+                }
                 let result = parser.result.as_mut().unwrap();
                 result.t = Some(CommandType::ACommand);
                 result.ar = Some(ACmdResult {
                     value: tokens[curr].repr.clone(),
                 });
                 parg.advance();
-                return Ok(parg);
+                Ok(parg)
             }
             _ => {
-                hack_report!(parg, "No number found")
+                hack_report!(parg, "E004", "No number found")
             }
         }
     }
@@ -315,9 +366,9 @@ impl Parser {
                     value: tokens[curr].repr.clone(),
                 });
                 parg.advance();
-                return Ok(parg);
+                Ok(parg)
             }
-            _ => hack_report!(parg, "No symbol found"),
+            _ => hack_report!(parg, "E004", "No symbol found"),
         }
     }
 
@@ -326,16 +377,20 @@ impl Parser {
     ) -> Result<&'a mut ParserArg<'a>, Box<HackError>> {
         let tokens = parg.tokens.as_ref().unwrap();
         let curr = **parg.index.as_ref().unwrap();
-        println!("{:?}", tokens[curr]);
+        #[cfg(feature = "std")]
+        if parg.parser.as_ref().unwrap().verbose {
+            println!("{:?}", tokens[curr]);
+        }
         match tokens[curr].token_type {
             TOKENTYPE::SYMBOL => {
-                if PREDEFINE_SYMBOLS.contains_key(&tokens[curr].repr) {
-                    hack_report!(parg, "Using reserved keyword as label is not allowed")
+                let symbols = &parg.parser.as_ref().unwrap().isa.symbols;
+                if symbols.contains_key(&tokens[curr].repr) {
+                    hack_report!(parg, "E005", "Using reserved keyword as label is not allowed")
                 }
                 parg.advance();
                 Ok(parg)
             }
-            _ => hack_report!(parg, "No label found"),
+            _ => hack_report!(parg, "E006", "No label found"),
         }
     }
 }
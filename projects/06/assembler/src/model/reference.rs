@@ -0,0 +1,338 @@
+use super::anatomy::Anatomy;
+use super::base::{COMP, DEST, JUMP, PREDEFINE_SYMBOLS};
+use super::isa::Isa;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+/**
+ * Instruction set reference
+ *
+ * `hack isa` prints the instruction formats, every COMP, DEST and JUMP
+ * code and the predefined symbols of an ISA as Markdown, HTML or JSON,
+ * so handouts are generated from the tables the assembler encodes with.
+ * Entries of the built-in tables keep their order in base.rs, the one of
+ * the book; entries added by a custom ISA follow, ordered by code. With
+ * the Hack layout the comp code is split into its a and c bits and the
+ * dest and jump codes are spelled out, see `Anatomy`.
+ */
+pub struct Reference {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Markdown,
+    Html,
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "markdown" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "Unknown format {}, expected markdown, html or json",
+                s
+            )),
+        }
+    }
+}
+
+struct Table {
+    name: &'static str,
+    header: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+/// `table` in the order of `base`, then by code.
+fn ordered(table: &BTreeMap<String, String>, base: &[(&str, &str)]) -> Vec<(String, String)> {
+    let rank = |k: &str| base.iter().position(|(b, _)| *b == k).unwrap_or(base.len());
+    let mut ret: Vec<(String, String)> =
+        table.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    ret.sort_by(|(k1, v1), (k2, v2)| (rank(k1), v1, k1).cmp(&(rank(k2), v2, k2)));
+    ret
+}
+
+/// `ordered`, led by the null entry of all zeros unless the table has one.
+fn with_null(table: &BTreeMap<String, String>, base: &[(&str, &str)]) -> Vec<(String, String)> {
+    let none = Isa::none(table);
+    let mut ret = ordered(table, base);
+    if !ret.iter().any(|(_, v)| *v == none) {
+        ret.insert(0, ("null".to_owned(), none));
+    }
+    ret
+}
+
+impl Reference {
+    pub fn render(isa: &Isa, format: Format) -> String {
+        match format {
+            Format::Markdown => Reference::markdown(isa),
+            Format::Html => Reference::html(isa),
+            Format::Json => Reference::json(isa),
+        }
+    }
+
+    /// Bit patterns of the A- and C-instruction.
+    fn formats(isa: &Isa) -> (String, String) {
+        let c = if Anatomy::hack_layout(isa) {
+            format!("{}accccccdddjjj", isa.prefix)
+        } else {
+            format!(
+                "{}{}{}{}",
+                isa.prefix,
+                "c".repeat(Isa::none(&isa.comp).len()),
+                "d".repeat(Isa::none(&isa.dest).len()),
+                "j".repeat(Isa::none(&isa.jump).len())
+            )
+        };
+        ("0vvvvvvvvvvvvvvv".to_owned(), c)
+    }
+
+    fn symbols(isa: &Isa) -> Vec<(String, i32)> {
+        let rank = |k: &str| {
+            PREDEFINE_SYMBOLS
+                .iter()
+                .position(|(b, _)| *b == k)
+                .unwrap_or(PREDEFINE_SYMBOLS.len())
+        };
+        let mut ret: Vec<(String, i32)> =
+            isa.symbols.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        ret.sort_by(|(k1, v1), (k2, v2)| (rank(k1), v1, k1).cmp(&(rank(k2), v2, k2)));
+        ret
+    }
+
+    fn tables(isa: &Isa) -> Vec<Table> {
+        let hack = Anatomy::hack_layout(isa);
+        let comp = ordered(&isa.comp, &COMP);
+        let dest = with_null(&isa.dest, &DEST);
+        let jump = with_null(&isa.jump, &JUMP);
+        vec![
+            Table {
+                name: "comp",
+                header: if hack {
+                    vec!["comp", "a", "c1..c6"]
+                } else {
+                    vec!["comp", "code"]
+                },
+                rows: comp
+                    .into_iter()
+                    .map(|(k, v)| match hack {
+                        true => vec![k, v[..1].to_owned(), v[1..].to_owned()],
+                        false => vec![k, v],
+                    })
+                    .collect(),
+            },
+            Table {
+                name: "dest",
+                header: if hack {
+                    vec!["dest", "d1 d2 d3", "stores out in"]
+                } else {
+                    vec!["dest", "code"]
+                },
+                rows: dest
+                    .into_iter()
+                    .map(|(k, v)| match hack {
+                        true => {
+                            let stores = match Anatomy::stores(&v) {
+                                stores if stores.is_empty() => "nothing".to_owned(),
+                                stores => stores.join(", "),
+                            };
+                            vec![k, v, stores]
+                        }
+                        false => vec![k, v],
+                    })
+                    .collect(),
+            },
+            Table {
+                name: "jump",
+                header: if hack {
+                    vec!["jump", "j1 j2 j3", "jumps if"]
+                } else {
+                    vec!["jump", "code"]
+                },
+                rows: jump
+                    .into_iter()
+                    .map(|(k, v)| match hack {
+                        true => {
+                            let conditions = Anatomy::conditions(&v);
+                            let conditions = match conditions.len() {
+                                0 => "never".to_owned(),
+                                3 => "always".to_owned(),
+                                _ => conditions.join(" or "),
+                            };
+                            vec![k, v, conditions]
+                        }
+                        false => vec![k, v],
+                    })
+                    .collect(),
+            },
+            Table {
+                name: "symbols",
+                header: vec!["symbol", "value"],
+                rows: Reference::symbols(isa)
+                    .into_iter()
+                    .map(|(k, v)| vec![k, v.to_string()])
+                    .collect(),
+            },
+        ]
+    }
+
+    pub fn markdown(isa: &Isa) -> String {
+        let (a, c) = Reference::formats(isa);
+        let mut ret = format!("# The {} instruction set\n\n", isa.name);
+        ret.push_str(&format!(
+            "A-instruction `@value`: `{}`, value is 0..32767 or a symbol\n\n",
+            a
+        ));
+        ret.push_str(&format!(
+            "C-instruction `dest=comp;jump`: `{}`, dest and jump are optional\n",
+            c
+        ));
+        let cell = |s: &String| format!("`{}`", s.replace('|', "\\|"));
+        for table in Reference::tables(isa) {
+            ret.push_str(&format!("\n## {}\n\n", table.name));
+            ret.push_str(&format!("| {} |\n", table.header.join(" | ")));
+            let rule: Vec<&str> = table.header.iter().map(|_| "---").collect();
+            ret.push_str(&format!("| {} |\n", rule.join(" | ")));
+            for row in &table.rows {
+                let mut cells = vec![cell(&row[0])];
+                cells.extend(row[1..].iter().cloned());
+                ret.push_str(&format!("| {} |\n", cells.join(" | ")));
+            }
+        }
+        ret
+    }
+
+    pub fn html(isa: &Isa) -> String {
+        let escape = |s: &str| {
+            s.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+        };
+        let (a, c) = Reference::formats(isa);
+        let title = format!("The {} instruction set", escape(&isa.name));
+        let mut ret = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n<body>\n<h1>{0}</h1>\n",
+            title
+        );
+        ret.push_str(&format!(
+            "<p>A-instruction <code>@value</code>: <code>{}</code>, value is 0..32767 or a symbol</p>\n",
+            a
+        ));
+        ret.push_str(&format!(
+            "<p>C-instruction <code>dest=comp;jump</code>: <code>{}</code>, dest and jump are optional</p>\n",
+            c
+        ));
+        for table in Reference::tables(isa) {
+            ret.push_str(&format!("<h2>{}</h2>\n<table>\n<tr>", table.name));
+            for h in &table.header {
+                ret.push_str(&format!("<th>{}</th>", h));
+            }
+            ret.push_str("</tr>\n");
+            for row in &table.rows {
+                ret.push_str(&format!("<tr><td><code>{}</code></td>", escape(&row[0])));
+                for cell in &row[1..] {
+                    ret.push_str(&format!("<td>{}</td>", escape(cell)));
+                }
+                ret.push_str("</tr>\n");
+            }
+            ret.push_str("</table>\n");
+        }
+        ret.push_str("</body>\n</html>\n");
+        ret
+    }
+
+    pub fn json(isa: &Isa) -> String {
+        let (a, c) = Reference::formats(isa);
+        let tables: Vec<(&str, serde_json::Value)> = Reference::tables(isa)
+            .into_iter()
+            .map(|table| {
+                let rows: Vec<serde_json::Value> = table
+                    .rows
+                    .iter()
+                    .map(|row| {
+                        let object: serde_json::Map<String, serde_json::Value> = table
+                            .header
+                            .iter()
+                            .zip(row)
+                            .map(|(h, cell)| match cell.parse::<i64>() {
+                                Ok(n) if table.name == "symbols" => (h.to_string(), json!(n)),
+                                _ => (h.to_string(), json!(cell)),
+                            })
+                            .collect();
+                        serde_json::Value::Object(object)
+                    })
+                    .collect();
+                (table.name, json!(rows))
+            })
+            .collect();
+        let mut object = json!({
+            "name": isa.name,
+            "a-instruction": a,
+            "c-instruction": c,
+        });
+        for (name, rows) in tables {
+            object[name] = rows;
+        }
+        serde_json::to_string_pretty(&object).unwrap() + "\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown() {
+        let text = Reference::markdown(&Isa::hack());
+        assert!(text.starts_with("# The hack instruction set\n"));
+        assert!(text.contains("`111accccccdddjjj`"));
+        assert!(text.contains("| `M+1` | 1 | 110111 |\n"));
+        assert!(text.contains("| `D\\|M` | 1 | 010101 |\n"));
+        assert!(text.contains("| `null` | 000 | nothing |\n| `M` | 001 | M |\n"));
+        assert!(text.contains("| `AMD` | 111 | A, D, M |\n"));
+        assert!(text.contains("| `JNE` | 101 | out < 0 or out > 0 |\n"));
+        assert!(text.contains("| `JMP` | 111 | always |\n"));
+        assert!(text.contains("| `SCREEN` | 16384 |\n"));
+        // every entry of every table is listed
+        let rows = text.lines().filter(|l| l.starts_with("| `")).count();
+        assert_eq!(
+            rows,
+            COMP.len() + DEST.len() + 1 + JUMP.len() + 1 + PREDEFINE_SYMBOLS.len()
+        );
+    }
+
+    #[test]
+    fn test_custom() {
+        let mut isa = Isa::hack();
+        isa.name = "shift".into();
+        isa.comp.insert("D<<1".into(), "0100000".into());
+        isa.symbols.insert("LED".into(), 24577);
+        let html = Reference::html(&isa);
+        assert!(html.contains("<tr><td><code>D&lt;&lt;1</code></td><td>0</td><td>100000</td></tr>"));
+        assert!(html.contains("<td><code>D&amp;M</code></td>"));
+        let json: serde_json::Value = serde_json::from_str(&Reference::json(&isa)).unwrap();
+        assert_eq!(json["name"], "shift");
+        let comp = json["comp"].as_array().unwrap();
+        assert_eq!(
+            comp[0],
+            json!({ "comp": "0", "a": "0", "c1..c6": "101010" })
+        );
+        assert_eq!(comp.last().unwrap()["comp"], "D<<1");
+        let symbols = json["symbols"].as_array().unwrap();
+        assert_eq!(
+            symbols.last().unwrap(),
+            &json!({ "symbol": "LED", "value": 24577 })
+        );
+        isa.prefix = "11".into();
+        isa.comp = isa
+            .comp
+            .iter()
+            .map(|(k, v)| (k.clone(), format!("0{}", v)))
+            .collect();
+        let json: serde_json::Value = serde_json::from_str(&Reference::json(&isa)).unwrap();
+        assert_eq!(json["c-instruction"], "11ccccccccdddjjj");
+        assert_eq!(json["jump"][7], json!({ "jump": "JMP", "code": "111" }));
+    }
+}
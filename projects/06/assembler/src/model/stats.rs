@@ -0,0 +1,145 @@
+use super::base::*;
+use super::hpu::HPU;
+use super::strutil::Strutil;
+use std::collections::{HashMap, HashSet};
+
+pub const TOP: usize = 10;
+
+/**
+ * Statistics
+ *
+ * Collected by the second pass over the expanded instructions: count by
+ * kind, ROM words against `ROM_SIZE`, variables allocated from `varmem`,
+ * the labels referenced most often and the most frequent dest=comp;jump
+ * combinations.
+ */
+#[derive(Default)]
+pub struct Stats {
+    pub a: usize,
+    pub c: usize,
+    pub labels: usize,
+    pub variables: usize,
+    pub label_uses: HashMap<String, usize>,
+    pub combos: HashMap<String, usize>,
+    known: HashSet<String>,
+}
+
+impl Stats {
+    /// `labels` are the label names found by the first pass.
+    pub fn new(labels: HashSet<String>) -> Stats {
+        Stats {
+            known: labels,
+            ..Stats::default()
+        }
+    }
+
+    pub fn add(&mut self, line: &str) {
+        match HPU::command_type(line) {
+            CommandType::ACommand => {
+                self.a += 1;
+                if self.known.contains(&line[1..]) {
+                    *self.label_uses.entry(line[1..].to_owned()).or_insert(0) += 1;
+                }
+            }
+            CommandType::LCommand => self.labels += 1,
+            CommandType::CCommand => {
+                self.c += 1;
+                let (dest, comp, jump) = Strutil::c_fields(line);
+                let mut combo = String::new();
+                if !dest.is_empty() {
+                    combo.push_str(&format!("{}=", dest));
+                }
+                combo.push_str(comp);
+                if !jump.is_empty() {
+                    combo.push_str(&format!(";{}", jump));
+                }
+                *self.combos.entry(combo).or_insert(0) += 1;
+            }
+        }
+    }
+
+    pub fn words(&self) -> usize {
+        self.a + self.c
+    }
+
+    /// The `n` most frequent entries, ties broken by name.
+    fn top(map: &HashMap<String, usize>, n: usize) -> Vec<(&str, usize)> {
+        let mut v: Vec<(&str, usize)> = map.iter().map(|(k, c)| (k.as_str(), *c)).collect();
+        v.sort_unstable_by(|x, y| y.1.cmp(&x.1).then(x.0.cmp(y.0)));
+        v.truncate(n);
+        v
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "instructions: {} (A {}, C {}), labels: {}",
+            self.words(),
+            self.a,
+            self.c,
+            self.labels
+        )?;
+        writeln!(
+            f,
+            "ROM: {} / {} words ({:.1}%)",
+            self.words(),
+            ROM_SIZE,
+            self.words() as f64 * 100.0 / ROM_SIZE as f64
+        )?;
+        match self.variables {
+            0 => writeln!(f, "variables: 0")?,
+            n => writeln!(
+                f,
+                "variables: {} (RAM {}..{})",
+                n,
+                VARMEM_BASE,
+                VARMEM_BASE + n - 1
+            )?,
+        }
+        writeln!(f, "most used labels:")?;
+        for (label, n) in Stats::top(&self.label_uses, TOP) {
+            writeln!(f, "{:>8} {}", n, label)?;
+        }
+        writeln!(f, "most frequent C-instructions:")?;
+        for (combo, n) in Stats::top(&self.combos, TOP) {
+            writeln!(f, "{:>8} {}", n, combo)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let labels: HashSet<String> = ["LOOP".to_owned(), "END".to_owned()]
+            .iter()
+            .cloned()
+            .collect();
+        let mut stats = Stats::new(labels);
+        for l in [
+            "(LOOP)", "@i", "M=M+1", "@LOOP", "D;JGT", "@LOOP", "0;JMP", "(END)", "@END", "0;JMP",
+        ]
+        .iter()
+        {
+            stats.add(l);
+        }
+        stats.variables = 1;
+        assert_eq!(
+            (stats.a, stats.c, stats.labels, stats.words()),
+            (4, 4, 2, 8)
+        );
+        assert_eq!(Stats::top(&stats.label_uses, 1), vec![("LOOP", 2)]);
+        assert_eq!(
+            Stats::top(&stats.combos, 2),
+            vec![("0;JMP", 2), ("D;JGT", 1)]
+        );
+        let report = format!("{}", stats);
+        assert!(report.contains("ROM: 8 / 32768 words (0.0%)\nvariables: 1 (RAM 16..16)\n"));
+        assert!(report.contains("most used labels:\n       2 LOOP\n       1 END\n"));
+    }
+}
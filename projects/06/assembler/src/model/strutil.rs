@@ -6,14 +6,41 @@ impl Strutil {
                 if i == 0 || i == ms.len() - 1 {
                     return false;
                 }
-                return true;
+                true
             }
             None => false,
         }
     }
     pub fn empty_line(s: &str) -> bool {
         let iter = s.split_whitespace();
-        return iter.count() == 0;
+        iter.count() == 0
+    }
+    /// Splits a line into its code and its `//` comment, if any. A `//`
+    /// inside a quoted string, e.g. of `.string`, is not a comment.
+    pub fn split_comment(s: &str) -> (&str, Option<&str>) {
+        let bytes = s.as_bytes();
+        let mut quoted = false;
+        for (i, b) in bytes.iter().enumerate() {
+            match b {
+                b'"' => quoted = !quoted,
+                b'/' if !quoted && bytes.get(i + 1) == Some(&b'/') => {
+                    return (s[..i].trim(), Some(&s[i..]));
+                }
+                _ => {}
+            }
+        }
+        (s.trim(), None)
+    }
+    /// Splits a C-instruction into its dest, comp and jump fields, empty if absent.
+    pub fn c_fields(code: &str) -> (&str, &str, &str) {
+        let (dest, rest) = match code.find('=') {
+            Some(i) => (&code[..i], &code[i + 1..]),
+            None => ("", code),
+        };
+        match rest.find(';') {
+            Some(i) => (dest, &rest[..i], &rest[i + 1..]),
+            None => (dest, rest, ""),
+        }
     }
 }
 
@@ -32,9 +59,22 @@ mod tests {
         assert!(!Strutil::fall_within(s1, ";"));
     }
 
-    pub fn empty_line(s: &str) -> bool {
-        let iter = s.split_whitespace();
-        return iter.count() == 0;
+    #[test]
+    fn test_split_comment() {
+        assert_eq!(
+            Strutil::split_comment("   D=M   // D = first number"),
+            ("D=M", Some("// D = first number"))
+        );
+        assert_eq!(Strutil::split_comment("// only"), ("", Some("// only")));
+        assert_eq!(Strutil::split_comment(" @R0 "), ("@R0", None));
+        assert_eq!(
+            Strutil::split_comment(".string 100 \"http://x\" // url"),
+            (".string 100 \"http://x\"", Some("// url"))
+        );
+        assert_eq!(
+            Strutil::split_comment(".assert X, \"a // b\""),
+            (".assert X, \"a // b\"", None)
+        );
     }
 
     #[test]
@@ -0,0 +1,91 @@
+use std::path::{Path, PathBuf};
+
+/**
+ * Watch mode
+ *
+ * Waits on inotify for one of the watched files to change. The parent
+ * directories are watched rather than the files, editors that save by
+ * writing a new file and renaming it over the old one are seen too.
+ * Changes arriving within `SETTLE` of the first are taken as one.
+ */
+pub struct Watch {}
+
+pub const SETTLE: std::time::Duration = std::time::Duration::from_millis(50);
+
+impl Watch {
+    fn parent(file: &Path) -> &Path {
+        match file.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        }
+    }
+
+    /// Blocks until one of `files` is written, created or renamed into place.
+    #[cfg(target_os = "linux")]
+    pub fn wait(files: &[PathBuf]) -> std::io::Result<PathBuf> {
+        use inotify::{Inotify, WatchMask};
+        let mut inotify = Inotify::init()?;
+        let mut dirs = Vec::new();
+        for file in files {
+            let dir = Watch::parent(file);
+            let wd = inotify.watches().add(
+                dir,
+                WatchMask::CLOSE_WRITE | WatchMask::CREATE | WatchMask::MOVED_TO,
+            )?;
+            dirs.push((wd, dir));
+        }
+        let mut buffer = [0; 4096];
+        loop {
+            for event in inotify.read_events_blocking(&mut buffer)? {
+                let name = match event.name {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let changed = files.iter().find(|f| {
+                    f.file_name() == Some(name)
+                        && dirs
+                            .iter()
+                            .any(|(wd, dir)| *wd == event.wd && *dir == Watch::parent(f))
+                });
+                if let Some(changed) = changed {
+                    std::thread::sleep(SETTLE);
+                    return Ok(changed.clone());
+                }
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn wait(_files: &[PathBuf]) -> std::io::Result<PathBuf> {
+        Err(std::io::Error::other(
+            "watch mode needs inotify, Linux only",
+        ))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait() {
+        let dir = std::env::temp_dir().join(format!("hack-{}-watch", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let watched = dir.join("Fill.asm");
+        let other = dir.join("Other.asm");
+        std::fs::write(&watched, "").unwrap();
+        let writer = {
+            let (watched, other) = (watched.clone(), other.clone());
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                std::fs::write(&other, "@0\n").unwrap();
+                // saved the way editors do, through a rename
+                let tmp = watched.with_extension("swp");
+                std::fs::write(&tmp, "@1\n").unwrap();
+                std::fs::rename(&tmp, &watched).unwrap();
+            })
+        };
+        assert_eq!(Watch::wait(std::slice::from_ref(&watched)).unwrap(), watched);
+        writer.join().unwrap();
+    }
+}
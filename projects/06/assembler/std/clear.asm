@@ -0,0 +1,26 @@
+// std/clear.asm: clears the screen, every pixel white.
+//
+// Returns nothing and clobbers D; R13, R14 and R15 are preserved.
+
+.export std.clear
+
+(std.clear)
+    @std.clear.ret
+    M=D
+    @SCREEN
+    D=A
+    @std.clear.p
+    M=D
+(std.clear.loop)
+    @std.clear.p
+    A=M
+    M=0
+    @std.clear.p
+    MD=M+1
+    @KBD
+    D=D-A
+    @std.clear.loop
+    D;JLT
+    @std.clear.ret
+    A=M
+    0;JMP
@@ -0,0 +1,70 @@
+// std/div.asm: R13 = R13 / R14 and R14 = R13 % R14.
+//
+// Long division over the 16 bits of R13, which is read as unsigned;
+// R14 must be between 1 and 32767. Dividing by 0 gives a quotient of -1
+// and leaves the dividend as the remainder. The quotient is also left
+// in D; R15 is preserved.
+
+.export std.div
+
+(std.div)
+    @std.div.ret
+    M=D
+    @std.div.q
+    M=0
+    @std.div.r
+    M=0
+    @16
+    D=A
+    @std.div.n
+    M=D
+(std.div.loop)
+    // r = 2r + the top bit of R13, then R13 and q move one place left
+    @std.div.r
+    D=M
+    M=D+M
+    @R13
+    D=M
+    @std.div.shift
+    D;JGE
+    @std.div.r
+    M=M+1
+(std.div.shift)
+    @R13
+    D=M
+    M=D+M
+    @std.div.q
+    D=M
+    M=D+M
+    // subtract when r >= R14, r below 0 has overflowed and is larger
+    @std.div.r
+    D=M
+    @std.div.sub
+    D;JLT
+    @R14
+    D=D-M
+    @std.div.next
+    D;JLT
+(std.div.sub)
+    @R14
+    D=M
+    @std.div.r
+    M=M-D
+    @std.div.q
+    M=M+1
+(std.div.next)
+    @std.div.n
+    MD=M-1
+    @std.div.loop
+    D;JGT
+    @std.div.r
+    D=M
+    @R14
+    M=D
+    @std.div.q
+    D=M
+    @R13
+    M=D
+    @std.div.ret
+    A=M
+    0;JMP
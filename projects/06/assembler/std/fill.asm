@@ -0,0 +1,30 @@
+// std/fill.asm: RAM[R13 .. R13+R14-1] = R15.
+//
+// Nothing is written when R14 is 0 or less. Returns nothing and clobbers
+// R13, R14 and D; R15 is preserved.
+
+.export std.fill
+
+(std.fill)
+    @std.fill.ret
+    M=D
+(std.fill.loop)
+    @R14
+    D=M
+    @std.fill.done
+    D;JLE
+    @R15
+    D=M
+    @R13
+    A=M
+    M=D
+    @R13
+    M=M+1
+    @R14
+    M=M-1
+    @std.fill.loop
+    0;JMP
+(std.fill.done)
+    @std.fill.ret
+    A=M
+    0;JMP
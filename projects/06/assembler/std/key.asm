@@ -0,0 +1,19 @@
+// std/key.asm: waits until a key is pressed, R13 = its code.
+//
+// The code is also left in D; R14 and R15 are preserved.
+
+.export std.key
+
+(std.key)
+    @std.key.ret
+    M=D
+(std.key.wait)
+    @KBD
+    D=M
+    @std.key.wait
+    D;JEQ
+    @R13
+    M=D
+    @std.key.ret
+    A=M
+    0;JMP
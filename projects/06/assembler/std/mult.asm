@@ -0,0 +1,42 @@
+// std/mult.asm: R13 = R13 * R14, modulo 2^16, negative operands included.
+//
+// Shift and add over the 16 bits of R14, so the time does not depend on
+// the operands. The result is also left in D; R14 and R15 are preserved.
+
+.export std.mult
+
+(std.mult)
+    @std.mult.ret
+    M=D
+    @std.mult.result
+    M=0
+    @std.mult.bit
+    M=1
+(std.mult.loop)
+    @std.mult.bit
+    D=M
+    @R14
+    D=D&M
+    @std.mult.skip
+    D;JEQ
+    @R13
+    D=M
+    @std.mult.result
+    M=D+M
+(std.mult.skip)
+    // R13 and the tested bit move one place left
+    @R13
+    D=M
+    M=D+M
+    @std.mult.bit
+    D=M
+    MD=D+M
+    @std.mult.loop
+    D;JNE
+    @std.mult.result
+    D=M
+    @R13
+    M=D
+    @std.mult.ret
+    A=M
+    0;JMP
@@ -0,0 +1,52 @@
+// std/rect.asm: draws a black rectangle R14 rows high and R15 words wide.
+//
+// R13 is the address of its top-left screen word, SCREEN + 32 * row +
+// column / 16; one word covers 16 pixels. Returns nothing and clobbers
+// R13, R14 and D; R15 is preserved.
+
+.export std.rect
+
+(std.rect)
+    @std.rect.ret
+    M=D
+(std.rect.row)
+    @R14
+    D=M
+    @std.rect.done
+    D;JLE
+    @R13
+    D=M
+    @std.rect.p
+    M=D
+    @R15
+    D=M
+    @std.rect.n
+    M=D
+(std.rect.word)
+    @std.rect.n
+    D=M
+    @std.rect.next
+    D;JLE
+    @std.rect.p
+    A=M
+    M=-1
+    @std.rect.p
+    M=M+1
+    @std.rect.n
+    M=M-1
+    @std.rect.word
+    0;JMP
+(std.rect.next)
+    // down one row of 32 words
+    @32
+    D=A
+    @R13
+    M=D+M
+    @R14
+    M=M-1
+    @std.rect.row
+    0;JMP
+(std.rect.done)
+    @std.rect.ret
+    A=M
+    0;JMP